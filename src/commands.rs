@@ -1,19 +1,31 @@
 //----------------------------------------
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     io::prelude::*,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
 };
 
 use base64::{engine::general_purpose, Engine as _};
 use serde::{Deserialize, Serialize};
-use unitypackage_util;
 
 use xxhash_rust::xxh64;
 
-use crate::package;
+use unitypackage_util::class_id;
+use unitypackage_util::deps::{self, DependencyGraph};
+use unitypackage_util::diff;
+use unitypackage_util::fbx2gltf::Fbx2Gltf;
+use unitypackage_util::hierarchy;
+use unitypackage_util::index::PackageIndex;
+use unitypackage_util::merge::{self, GuidConflict, PathnameConflict};
+use unitypackage_util::package::{self, ReadLimits};
+use unitypackage_util::prefab::PrefabSources;
+use unitypackage_util::scripts::ScriptIndex;
+use unitypackage_util::texture::{self, ImporterSettings, TextureFormat};
+use unitypackage_util::unity_yaml;
+use unitypackage_util::validate;
+use unitypackage_util::writer::PackageWriter;
+use unitypackage_util::{Error, Result};
 
 //----------------------------------------
 
@@ -23,71 +35,221 @@ struct Dump {
     content_type: Option<String>,
     asset: Option<serde_yaml::Value>,
     asset_meta: Option<serde_yaml::Value>,
+    /// Has a preview.png thumbnail, with `preview`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preview: Option<bool>,
+    /// Documents skipped in recovery mode
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<DumpError>,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Serialize)]
+struct DumpError {
+    /// "asset" or "asset.meta"
+    file: String,
+    /// Document index within the file
+    index: usize,
+    file_id: Option<String>,
+    line: Option<usize>,
+    message: String,
+}
+
+//----------------------------------------
+
+impl DumpError {
+    fn whole_file(file: &str, err: Error) -> DumpError {
+        DumpError {
+            file: file.to_owned(),
+            index: 0,
+            file_id: None,
+            line: None,
+            message: err.to_string(),
+        }
+    }
+}
+
+//----------------------------------------
+
+#[derive(Debug, Default, Serialize)]
+struct ClassCount {
+    /// Name from the class ID table, None if unknown
+    name: Option<&'static str>,
+    count: usize,
+    /// Type keys seen for this class ID and their counts
+    types: BTreeMap<String, usize>,
+    /// A type key did not match the table
+    mismatch: bool,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Serialize)]
+struct PackageInfo {
+    package: String,
+    /// "Folder", "Tar", "TarGz", "Zstd", "Xz", "Bzip2", or "Zip"
+    r#type: &'static str,
+    assets: usize,
+    folders: usize,
+    previews: usize,
+    /// Sum of all entry sizes
+    uncompressed_size: u64,
+    /// Size of the package file, None for folders
+    compressed_size: Option<u64>,
+    /// Lowercase extension of each asset's pathname, "" for none
+    extensions: BTreeMap<String, usize>,
+    /// Detected type of each asset's data, "unknown" when nothing matched
+    content_types: BTreeMap<String, usize>,
+    largest: Vec<AssetSize>,
+    file_format_versions: BTreeMap<String, usize>,
+    license_types: BTreeMap<String, usize>,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Serialize)]
+struct AssetSize {
+    guid: String,
+    pathname: Option<String>,
+    size: u64,
+}
+
+//----------------------------------------
+
+/// What `package_info` collects per guid
+#[derive(Debug, Default)]
+struct InfoEntry {
+    pathname: Option<String>,
+    asset_size: Option<u64>,
+    content_type: Option<String>,
+    preview: bool,
+    folder: bool,
+    file_format_version: Option<String>,
+    license_type: Option<String>,
+}
+
+//----------------------------------------
+
+/// `-` reads the package from stdin, `limits` are --hardened's
+fn open_package(package_file: &str, limits: Option<ReadLimits>) -> Result<package::Package> {
+    let package = match package_file {
+        package::STDIN_PATH => stdin_package(limits)?,
+        _ => package::Package::new(package_file)?,
+    };
+    Ok(match limits {
+        Some(limits) => package.hardened(limits),
+        None => package,
+    })
+}
+
+//----------------------------------------
+
+fn stdin_package(limits: Option<ReadLimits>) -> Result<package::Package> {
+    let stdin = std::io::stdin().lock();
+    let limits = match limits {
+        Some(limits) => limits,
+        None => return package::Package::from_reader(stdin),
+    };
+
+    // The total size limit also caps the compressed input
+    let package = package::Package::from_reader(stdin.take(limits.max_total_size + 1))?;
+    if package.archive_size()?.unwrap_or(0) > limits.max_total_size {
+        return Err(Error::PackageTooLarge(format!(
+            "over {} bytes on stdin",
+            limits.max_total_size
+        )));
+    }
+    Ok(package)
+}
+
+//----------------------------------------
+
+/// Stdin can only be read once, for one of the packages
+fn check_stdin_once(package_files: &[&Path]) -> Result<()> {
+    let stdin = Path::new(package::STDIN_PATH);
+    if package_files.iter().filter(|file| **file == stdin).count() > 1 {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "only one package can be read from stdin",
+        )));
+    }
+    Ok(())
 }
 
 //----------------------------------------
 
 fn yaml_matcher(buf: &[u8]) -> bool {
     let sig = b"%YAML";
-    return buf.len() >= sig.len() && buf[0..sig.len()].cmp(sig) == std::cmp::Ordering::Equal;
+    buf.len() >= sig.len() && buf[0..sig.len()].cmp(sig) == std::cmp::Ordering::Equal
 }
 
 //----------------------------------------
 
+/// Binary, or ASCII with its "; FBX 7.4.0 project file" header
 fn fbx_matcher(buf: &[u8]) -> bool {
-    let sig = b"Kaydara FBX Binary";
-    return buf.len() >= sig.len() && buf[0..sig.len()].cmp(sig) == std::cmp::Ordering::Equal;
+    buf.starts_with(b"Kaydara FBX Binary") || buf.starts_with(b"; FBX")
 }
 
 //----------------------------------------
 
-pub fn deserializer(yaml: &String) -> serde_yaml::Value {
-    serde_yaml::Deserializer::from_str(&yaml)
-        .map(|doc| <serde_yaml::Value>::deserialize(doc).unwrap())
-        .collect()
+/// `infer` with the YAML and FBX matchers added
+fn content_types() -> infer::Infer {
+    let mut info = infer::Infer::new();
+    info.add("text/yaml", "yaml", yaml_matcher);
+    info.add("data/fbx", "fbx", fbx_matcher);
+    // info.add("text/json", "json", json_matcher);
+    info
 }
 
-/*
-// This allows it to continue but removes the whole sub-document if it fails to deserialize
-// unitypackage_godot needs to be update to handle this before implementing
-
-    serde_yaml::Deserializer::from_str(&yaml)
-        .map(|doc| {
-            let res = <serde_yaml::Value>::deserialize(doc);
-            match res {
-                Ok(res) => res,
-                Err(_e) => {
-                    // std::io::stderr()
-                    //     .write_all(format!("Deserialize Error: {:?}\n", e).as_bytes())
-                    //     .unwrap();
-                    serde_yaml::Value::String("".to_owned())
-                }
-            }
-        })
+//----------------------------------------
+
+pub fn deserializer(yaml: &str) -> Result<serde_yaml::Value> {
+    serde_yaml::Deserializer::from_str(yaml)
+        .map(|doc| Ok(<serde_yaml::Value>::deserialize(doc)?))
         .collect()
- */
+}
+
+//----------------------------------------
+
+fn asset_meta_value(yaml: &str) -> Result<serde_yaml::Value> {
+    deserializer(&unitypackage_util::asset_meta_yaml_cleanup(yaml)?)
+}
+
 //----------------------------------------
 
-pub fn package_contents_dump(package_file: &str, pretty: bool, debug: bool) {
+/// With `recover`, documents that fail to parse are left out and recorded in
+/// each entry's `errors` instead of aborting. `debug` implies `recover` and
+/// prints the error report instead of the dump. `preview` marks which assets
+/// have a thumbnail.
+pub fn package_contents_dump(
+    package_file: &str,
+    limits: Option<ReadLimits>,
+    pretty: bool,
+    recover: bool,
+    resolve_scripts: bool,
+    preview: bool,
+    debug: bool,
+) -> Result<()> {
+    let recover = recover || debug;
+    let package = open_package(package_file, limits)?;
+
+    let scripts = if resolve_scripts {
+        Some(ScriptIndex::build(&package)?)
+    } else {
+        None
+    };
+
     let mut data = HashMap::<String, Dump>::new();
 
-    let mut info = infer::Infer::new();
-    info.add("text/yaml", "yaml", yaml_matcher);
-    info.add("data/fbx", "fbx", fbx_matcher);
-    // info.add("text/json", "json", json_matcher);
+    let info = content_types();
 
-    for file in package::Package::new(package_file)
-        .unwrap()
-        .open()
-        .unwrap()
-        .entries()
-    {
-        let mut file = file.unwrap();
+    for file in package.open()?.entries()? {
+        let mut file = file?;
 
-        let file_path = file.path().unwrap().to_str().unwrap().to_owned();
-        let size = file.size().unwrap();
+        let file_path = file.path()?.to_string_lossy().to_string();
 
-        let guid = file.guid();
+        let guid = file.guid()?;
         if guid.len() < 32 {
             continue;
         }
@@ -97,270 +259,1511 @@ pub fn package_contents_dump(package_file: &str, pretty: bool, debug: bool) {
             content_type: None,
             asset: None,
             asset_meta: None,
+            preview: preview.then_some(false),
+            errors: Vec::new(),
         });
 
+        if preview && file_path.ends_with("/preview.png") {
+            entry.preview = Some(true);
+        }
+
         // println!("checking pathname");
         if file_path.ends_with("/pathname") {
-            if debug {
-                println!("{}/pathname", guid);
-            }
-
             let mut s = String::new();
-            file.read_to_string(&mut s).unwrap();
+            file.read_to_string(&mut s)?;
 
             entry.pathname = Some(s.split("\n").next().unwrap().to_owned());
         }
 
         // println!("checking asset.meta");
         if file_path.ends_with("/asset.meta") {
-            if debug {
-                println!("{}/asset.meta", guid);
-            }
-
             let mut yaml = String::new();
-            file.read_to_string(&mut yaml).unwrap();
-
-            let yaml = unitypackage_util::asset_meta_yaml_cleanup(&yaml);
-            let deserialized = deserializer(&yaml);
+            file.read_to_string(&mut yaml)?;
 
-            entry.asset_meta = Some(deserialized);
+            match asset_meta_value(&yaml) {
+                Ok(value) => entry.asset_meta = Some(value),
+                Err(err) if recover => entry.errors.push(DumpError::whole_file("asset.meta", err)),
+                Err(err) => return Err(err),
+            }
         }
 
         // println!("checking asset");
         if file_path.ends_with("/asset") {
-            if debug {
-                println!("{}/asset", guid);
-            }
-
-            let mut buffer = Vec::with_capacity(size);
-            file.read_to_end(&mut buffer).unwrap();
+            let mut buffer = Vec::new();
+            file.read_to_end(&mut buffer)?;
 
             if let Some(content_type) = info.get(&buffer) {
                 entry.content_type = Some(content_type.mime_type().to_owned());
             }
 
             if buffer.starts_with(b"%YAML") {
-                let yaml = std::str::from_utf8(&buffer).unwrap();
-                let yaml = unitypackage_util::asset_yaml_cleanup(&yaml);
-                let deserialized = deserializer(&yaml);
+                let yaml = match asset_str(&buffer, &guid) {
+                    Ok(yaml) => yaml,
+                    Err(err) if recover => {
+                        entry.errors.push(DumpError::whole_file("asset", err));
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+
+                let documents = if recover {
+                    let (documents, errors) = unity_yaml::parse_recover(yaml);
+                    entry.errors.extend(errors.into_iter().map(|err| DumpError {
+                        file: "asset".to_owned(),
+                        index: err.index,
+                        file_id: err.file_id.map(|file_id| file_id.to_string()),
+                        line: Some(err.line),
+                        message: err.message,
+                    }));
+                    documents
+                } else {
+                    unity_yaml::parse(yaml)?
+                };
 
-                entry.asset = Some(deserialized);
+                let mut documents = unity_yaml::documents_to_value(&documents);
+                if let Some(scripts) = &scripts {
+                    scripts.annotate(&mut documents);
+                }
+                entry.asset = Some(documents);
             }
         }
     }
 
-    if !debug {
-        println!("{}", serde_json_to_string(&data, pretty));
+    if debug {
+        dump_error_report(&data)
+    } else {
+        writeln!(
+            std::io::stdout(),
+            "{}",
+            serde_json_to_string(&data, pretty)?
+        )?;
+        Ok(())
+    }
+}
+
+//----------------------------------------
+
+fn dump_error_report(data: &HashMap<String, Dump>) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    let sorted = data
+        .iter()
+        .filter(|(_, entry)| !entry.errors.is_empty())
+        .collect::<BTreeMap<_, _>>();
+
+    for (guid, entry) in &sorted {
+        writeln!(
+            stdout,
+            "{} {}",
+            guid,
+            entry.pathname.as_deref().unwrap_or("(no pathname)")
+        )?;
+
+        for err in &entry.errors {
+            write!(stdout, "  {} document {}", err.file, err.index)?;
+            if let Some(file_id) = &err.file_id {
+                write!(stdout, " &{}", file_id)?;
+            }
+            if let Some(line) = err.line {
+                write!(stdout, " line {}", line)?;
+            }
+            writeln!(stdout, ": {}", err.message)?;
+        }
     }
+
+    let count = sorted
+        .values()
+        .map(|entry| entry.errors.len())
+        .sum::<usize>();
+    writeln!(stdout, "{} errors in {} assets", count, sorted.len())?;
+    Ok(())
 }
 
 //----------------------------------------
 
-pub fn package_contents_name(package_file: &str, guid: &str) {
+pub fn package_contents_name(
+    package_file: &str,
+    limits: Option<ReadLimits>,
+    guid: &str,
+) -> Result<()> {
     let looking_for = format!("{}/pathname", guid);
 
-    for file in package::Package::new(package_file)
-        .unwrap()
-        .open()
-        .unwrap()
-        .entries()
-    {
-        let mut file = file.unwrap();
+    let package = open_package(package_file, limits)?;
+
+    if let Some(index) = PackageIndex::load_sidecar(&package)? {
+        let pathname = index
+            .guids
+            .get(guid)
+            .and_then(|entry| entry.pathname.as_ref());
+        return match pathname {
+            Some(pathname) => Ok(writeln!(std::io::stdout(), "{}", pathname)?),
+            None => Err(Error::MissingGuid(looking_for)),
+        };
+    }
 
-        let file_path = file.path().unwrap().to_str().unwrap().to_owned();
-        // let size = file.size().unwrap();
+    for file in package.open()?.entries()? {
+        let mut file = file?;
+
+        let file_path = file.path()?.to_string_lossy().to_string();
+        // let size = file.size()?;
 
         if file_path == looking_for {
             let mut s = String::new();
-            file.read_to_string(&mut s).unwrap();
+            file.read_to_string(&mut s)?;
+
+            writeln!(std::io::stdout(), "{}", s.split("\n").next().unwrap())?;
+            return Ok(());
+        }
+    }
+
+    Err(Error::MissingGuid(looking_for))
+}
+
+//----------------------------------------
+
+const INFO_LARGEST: usize = 10;
+
+/// infer only looks at the start of a file
+const CONTENT_TYPE_PREFIX: u64 = 8192;
+
+//----------------------------------------
+
+pub fn package_info(
+    package_file: &str,
+    limits: Option<ReadLimits>,
+    json: bool,
+    pretty: bool,
+) -> Result<()> {
+    let package = open_package(package_file, limits)?;
+    let info = content_types();
+
+    let mut entries = BTreeMap::<String, InfoEntry>::new();
+    let mut uncompressed_size = 0u64;
+
+    for file in package.clone().open()?.entries()? {
+        let mut file = file?;
+
+        let size = file.size()?;
+        if !file.is_dir() {
+            uncompressed_size += size as u64;
+        }
+
+        let guid = file.guid()?;
+        if guid.len() < 32 {
+            continue;
+        }
+
+        let file_name = file.file_name()?;
+        let entry = entries.entry(guid).or_default();
+
+        match file_name.as_str() {
+            "pathname" => {
+                let mut s = String::new();
+                file.read_to_string(&mut s)?;
+                entry.pathname = Some(s.split("\n").next().unwrap().to_owned());
+            }
+            "asset" => {
+                let mut buffer = Vec::new();
+                file.read_prefix(CONTENT_TYPE_PREFIX, &mut buffer)?;
+                entry.asset_size = Some(size as u64);
+                entry.content_type = info.get(&buffer).map(|kind| kind.mime_type().to_owned());
+            }
+            "asset.meta" => {
+                let mut buffer = Vec::new();
+                file.read_to_end(&mut buffer)?;
+                entry.folder = unitypackage_util::asset_meta_is_folder(&buffer);
+
+                let meta = String::from_utf8_lossy(&buffer);
+                let value = |key: &str| {
+                    meta.lines()
+                        .find_map(|line| line.strip_prefix(key))
+                        .map(|value| value.trim().to_owned())
+                };
+                entry.file_format_version = value("fileFormatVersion:");
+                entry.license_type = value("licenseType:");
+            }
+            "preview.png" => entry.preview = true,
+            _ => (),
+        }
+    }
+
+    let mut stats = PackageInfo {
+        package: package.path().display().to_string(),
+        r#type: package.kind(),
+        assets: 0,
+        folders: 0,
+        previews: 0,
+        uncompressed_size,
+        compressed_size: package.archive_size()?,
+        extensions: BTreeMap::new(),
+        content_types: BTreeMap::new(),
+        largest: Vec::new(),
+        file_format_versions: BTreeMap::new(),
+        license_types: BTreeMap::new(),
+    };
+
+    for (guid, entry) in &entries {
+        if entry.preview {
+            stats.previews += 1;
+        }
+        if let Some(version) = &entry.file_format_version {
+            *stats
+                .file_format_versions
+                .entry(version.clone())
+                .or_default() += 1;
+        }
+        if let Some(license) = &entry.license_type {
+            *stats.license_types.entry(license.clone()).or_default() += 1;
+        }
+
+        if entry.folder {
+            stats.folders += 1;
+            continue;
+        }
+        let size = match entry.asset_size {
+            Some(size) => size,
+            None => continue,
+        };
+
+        stats.assets += 1;
+
+        let extension = entry
+            .pathname
+            .as_ref()
+            .and_then(|pathname| Path::new(pathname).extension())
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        *stats.extensions.entry(extension).or_default() += 1;
+
+        let content_type = entry.content_type.as_deref().unwrap_or("unknown");
+        *stats
+            .content_types
+            .entry(content_type.to_owned())
+            .or_default() += 1;
+
+        stats.largest.push(AssetSize {
+            guid: guid.clone(),
+            pathname: entry.pathname.clone(),
+            size,
+        });
+    }
 
-            println!("{}", s.split("\n").next().unwrap());
-            return;
+    stats.largest.sort_by(|a, b| {
+        b.size
+            .cmp(&a.size)
+            .then_with(|| a.pathname.cmp(&b.pathname))
+    });
+    stats.largest.truncate(INFO_LARGEST);
+
+    if json {
+        writeln!(
+            std::io::stdout(),
+            "{}",
+            serde_json_to_string(&stats, pretty)?
+        )?;
+    } else {
+        write_info(&package, &stats)?;
+    }
+
+    Ok(())
+}
+
+//----------------------------------------
+
+fn write_info(package: &package::Package, stats: &PackageInfo) -> Result<()> {
+    let mut out = std::io::stdout().lock();
+
+    writeln!(out, "Package: {}", package)?;
+    writeln!(out, "Assets: {}", stats.assets)?;
+    writeln!(out, "Folders: {}", stats.folders)?;
+    writeln!(out, "Previews: {}", stats.previews)?;
+    match stats.compressed_size {
+        Some(compressed) => writeln!(
+            out,
+            "Size: {} bytes, {} compressed",
+            stats.uncompressed_size, compressed
+        )?,
+        None => writeln!(out, "Size: {} bytes", stats.uncompressed_size)?,
+    }
+
+    let counts = [
+        ("Extensions", &stats.extensions),
+        ("Content types", &stats.content_types),
+        ("fileFormatVersion", &stats.file_format_versions),
+        ("licenseType", &stats.license_types),
+    ];
+    for (title, counts) in counts {
+        if counts.is_empty() {
+            continue;
+        }
+        writeln!(out, "{}:", title)?;
+        for (name, count) in counts {
+            let name = if name.is_empty() { "(none)" } else { name };
+            writeln!(out, "  {:>6}  {}", count, name)?;
         }
     }
 
-    std::io::stderr()
-        .write_all(format!("Could not find {} in package\n", looking_for).as_bytes())
-        .unwrap();
-    std::process::exit(exitcode::NOINPUT);
+    if !stats.largest.is_empty() {
+        writeln!(out, "Largest:")?;
+        for asset in &stats.largest {
+            writeln!(
+                out,
+                "  {:>10}  {}",
+                asset.size,
+                asset.pathname.as_deref().unwrap_or(&asset.guid)
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+//----------------------------------------
+
+/// `package_file` is the old package, `other` the new one
+pub fn package_diff(
+    package_file: &str,
+    limits: Option<ReadLimits>,
+    other: &Path,
+    yaml: bool,
+    pretty: bool,
+) -> Result<()> {
+    check_stdin_once(&[Path::new(package_file), other])?;
+
+    let old = open_package(package_file, limits)?;
+    let new = open_package(&other.to_string_lossy(), limits)?;
+
+    let diff = diff::diff_packages(&old, &new, yaml)?;
+    writeln!(
+        std::io::stdout(),
+        "{}",
+        serde_json_to_string(&diff, pretty)?
+    )?;
+
+    Ok(())
 }
 
 //----------------------------------------
 
+/// `preview` appends whether each asset has a preview.png thumbnail
 pub fn package_contents_list(
     package_file: &str,
+    limits: Option<ReadLimits>,
     dir: &Option<String>,
     with_guids: bool,
+    preview: bool,
     pretty: bool,
-) {
+) -> Result<()> {
     let mut contents = Vec::new();
+    let mut previews = HashSet::new();
 
-    for file in package::Package::new(package_file)
-        .unwrap()
-        .open()
-        .unwrap()
-        .entries()
-    {
-        let mut file = file.unwrap();
+    for file in open_package(package_file, limits)?.open()?.entries()? {
+        let mut file = file?;
 
-        let file_path = file.path().unwrap().to_str().unwrap().to_owned();
-        // let size = file.size().unwrap();
+        let file_path = file.path()?.to_string_lossy().to_string();
+        // let size = file.size()?;
 
-        let guid = file.guid();
+        let guid = file.guid()?;
         if guid.len() < 32 {
             continue;
         }
 
+        if file_path.ends_with("/preview.png") {
+            previews.insert(guid.clone());
+        }
+
         if file_path.ends_with("/pathname") {
             let mut pathname = String::new();
-            file.read_to_string(&mut pathname).unwrap();
+            file.read_to_string(&mut pathname)?;
 
             let mut include = true;
 
             if let Some(dir) = dir {
-                let tmp_file_path = format!("{}", pathname.split("\n").next().unwrap());
+                let tmp_file_path = pathname.split("\n").next().unwrap().to_string();
 
                 let path = Path::new(&tmp_file_path);
-                let parent = path.parent().unwrap().to_str().unwrap();
+                let parent = path.parent().unwrap_or(Path::new(""));
 
                 //println!("Looking for {} in {} || {}", dir, s, parent);
                 include = parent == dir;
             }
 
             if include {
-                contents.push([guid.to_owned(), pathname.split("\n").next().unwrap().to_owned()]);
+                contents.push([
+                    guid.to_owned(),
+                    pathname.split("\n").next().unwrap().to_owned(),
+                ]);
             }
         }
     }
 
     contents.sort_by(|a, b| a[1].cmp(&b[1]));
 
-    if with_guids {
-        print!("{}", serde_json_to_string(&contents, pretty));
+    if preview {
+        // [guid, pathname, has_preview], or [pathname, has_preview]
+        let output = contents
+            .iter()
+            .map(|[guid, pathname]| {
+                let has_preview = previews.contains(guid);
+                if with_guids {
+                    serde_json::json!([guid, pathname, has_preview])
+                } else {
+                    serde_json::json!([pathname, has_preview])
+                }
+            })
+            .collect::<Vec<serde_json::Value>>();
+        write!(
+            std::io::stdout(),
+            "{}",
+            serde_json_to_string(&output, pretty)?
+        )?;
+    } else if with_guids {
+        write!(
+            std::io::stdout(),
+            "{}",
+            serde_json_to_string(&contents, pretty)?
+        )?;
     } else {
         let output = contents
             .iter()
             .map(|item| item[1].to_owned())
             .collect::<Vec<String>>();
-        print!("{}", serde_json_to_string(&output, pretty));
+        write!(
+            std::io::stdout(),
+            "{}",
+            serde_json_to_string(&output, pretty)?
+        )?;
     }
+
+    Ok(())
 }
 
 //----------------------------------------
 
-fn serde_json_to_string<T: Serialize>(json: &T, pretty: bool) -> String {
+fn serde_json_to_string<T: Serialize>(json: &T, pretty: bool) -> Result<String> {
     let printer = if pretty {
         serde_json::to_string_pretty
     } else {
         serde_json::to_string
     };
 
-    printer(&json).unwrap()
+    Ok(printer(&json)?)
+}
+
+//----------------------------------------
+
+fn asset_str<'a>(buffer: &'a [u8], guid: &str) -> Result<&'a str> {
+    std::str::from_utf8(buffer)
+        .map_err(|err| Error::MalformedYaml(format!("{}/asset is not UTF-8: {}", guid, err)))
 }
 
 //----------------------------------------
 
-fn yaml_to_json<'a, T: Deserialize<'a> + Serialize>(yaml: &'a str, pretty: bool) -> String {
-    let output = serde_yaml::Deserializer::from_str(&yaml)
-        .map(|doc| T::deserialize(doc).unwrap())
-        .map(|doc| serde_json_to_string(&doc, pretty))
-        .collect::<Vec<String>>()
-        .join(",");
+pub struct ExtractOptions {
+    pub output_file: Option<PathBuf>,
+    pub force: bool,
+    pub meta: bool,
+    pub json: bool,
+    pub pretty: bool,
+    pub fbx2gltf: bool,
+    pub converter: Fbx2Gltf,
+    pub base64: bool,
+    /// Annotate MonoBehaviours with their script, with `json`
+    pub resolve_scripts: bool,
+    /// Re-encode texture sources, following their TextureImporter settings
+    pub convert_texture: Option<TextureFormat>,
+    /// Extract /preview.png instead of /asset
+    pub preview: bool,
+}
+
+//----------------------------------------
 
-    format!("[{}]]\n", output)
+impl ExtractOptions {
+    /// The entry below the guid folder to extract
+    fn file_name(&self) -> &'static str {
+        if self.meta {
+            "asset.meta"
+        } else if self.preview {
+            "preview.png"
+        } else {
+            "asset"
+        }
+    }
 }
 
 //----------------------------------------
 
 pub fn package_file_extract(
     package_file: &str,
+    limits: Option<ReadLimits>,
+    guid: &str,
+    options: &ExtractOptions,
+) -> Result<()> {
+    let looking_for = format!("{}/{}", guid, options.file_name());
+    let looking_for_pathname = format!("{}/pathname", guid);
+
+    // Writing into a directory names the file after the asset's pathname
+    let to_dir = options
+        .output_file
+        .as_ref()
+        .is_some_and(|path| path.is_dir() || path.to_string_lossy().ends_with(['/', '\\']));
+
+    if let Some(output_file) = &options.output_file {
+        if !to_dir {
+            check_overwrite(output_file, options.force)?;
+        }
+    }
+
+    let package = open_package(package_file, limits)?;
+    let scripts = extract_scripts(&package, options)?;
+
+    // Texture conversion goes by the pathname's extension
+    let with_pathname = to_dir || options.convert_texture.is_some();
+
+    let (buffer, pathname) = match PackageIndex::load_sidecar(&package)? {
+        Some(index) => {
+            let (guid, file_name) = looking_for.split_once('/').unwrap();
+            let pathname = index
+                .guids
+                .get(guid)
+                .and_then(|entry| entry.pathname.clone());
+            (Some(index.read(&package, guid, file_name)?), pathname)
+        }
+        None => find_entry(
+            package.clone(),
+            &looking_for,
+            &looking_for_pathname,
+            with_pathname,
+        )?,
+    };
+
+    let buffer = buffer.ok_or(Error::MissingGuid(looking_for))?;
+
+    let output = if options.json {
+        let documents = extract_documents(&buffer, guid, options.meta, scripts.as_ref())?;
+        format!("{}\n", serde_json_to_string(&documents, options.pretty)?).into_bytes()
+    } else {
+        let metas = extract_metas(&package, [(guid, &pathname)], options)?;
+        extract_convert(buffer, &pathname, metas.get(guid), options)?
+    };
+    let output = extract_encode(output, options);
+
+    match &options.output_file {
+        None => std::io::stdout().write_all(&output)?,
+        Some(output_file) => {
+            let output_file = if to_dir {
+                let output_file = output_file.join(extract_file_name(guid, &pathname, options));
+                check_overwrite(&output_file, options.force)?;
+                output_file
+            } else {
+                output_file.to_owned()
+            };
+
+            if let Some(parent) = output_file.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(output_file, output)?;
+        }
+    }
+
+    Ok(())
+}
+
+//----------------------------------------
+
+pub struct ExtractSelection {
+    pub guids: Vec<String>,
+    pub guids_from: Option<PathBuf>,
+    pub glob: Option<glob::Pattern>,
+    pub regex: Option<regex::Regex>,
+}
+
+//----------------------------------------
+
+impl ExtractSelection {
+    /// A lone GUID keeps the original single file extract output
+    pub fn single_guid(&self) -> Option<&str> {
+        match (&self.guids[..], &self.guids_from, &self.glob, &self.regex) {
+            ([guid], None, None, None) => Some(guid),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, pathname: &str) -> bool {
+        self.glob
+            .as_ref()
+            .is_some_and(|glob| glob.matches(pathname))
+            || self.regex.as_ref().is_some_and(|re| re.is_match(pathname))
+    }
+}
+
+//----------------------------------------
+
+pub fn package_batch_extract(
+    package_file: &str,
+    limits: Option<ReadLimits>,
+    selection: &ExtractSelection,
+    options: &ExtractOptions,
+) -> Result<()> {
+    let file_name = options.file_name();
+
+    let mut guids = selection.guids.iter().cloned().collect::<HashSet<String>>();
+    if let Some(guids_from) = &selection.guids_from {
+        if guids_from == Path::new("-") && package_file == package::STDIN_PATH {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the package and --guids-from can't both be read from stdin",
+            )));
+        }
+        guids.extend(read_guid_list(guids_from)?);
+    }
+
+    let selected = |guid: &str, pathname: Option<&String>| {
+        guids.contains(guid) || pathname.is_some_and(|pathname| selection.matches(pathname))
+    };
+
+    let to_dir = options
+        .output_file
+        .as_ref()
+        .is_some_and(|path| path.is_dir() || path.to_string_lossy().ends_with(['/', '\\']));
+
+    if let Some(output_file) = &options.output_file {
+        if !to_dir {
+            check_overwrite(output_file, options.force)?;
+        }
+    }
+
+    // guid -> (pathname, data)
+    let mut found = BTreeMap::<String, (Option<String>, Vec<u8>)>::new();
+
+    let package = open_package(package_file, limits)?;
+    let scripts = extract_scripts(&package, options)?;
+
+    if let Some(index) = PackageIndex::load_sidecar(&package)? {
+        let wanted = index
+            .guids
+            .iter()
+            .filter(|(guid, entry)| {
+                entry.files.contains_key(file_name) && selected(guid, entry.pathname.as_ref())
+            })
+            .map(|(guid, _entry)| (guid.to_owned(), file_name.to_owned()))
+            .collect::<Vec<_>>();
+
+        for ((guid, _file_name), buffer) in wanted.iter().zip(index.read_many(&package, &wanted)?) {
+            let pathname = index.guids[guid].pathname.clone();
+            found.insert(guid.to_owned(), (pathname, buffer));
+        }
+    } else {
+        let mut pathnames = HashMap::<String, String>::new();
+        // Data seen before its guid/pathname, kept until the pathname decides
+        let mut pending = HashMap::<String, Vec<u8>>::new();
+
+        for file in package.clone().open()?.entries()? {
+            let mut file = file?;
+
+            let guid = file.guid()?;
+            if guid.len() < 32 {
+                continue;
+            }
+
+            let entry_name = file.file_name()?;
+
+            if entry_name == "pathname" {
+                let mut s = String::new();
+                file.read_to_string(&mut s)?;
+                let pathname = s.split("\n").next().unwrap().to_owned();
+
+                match found.get_mut(&guid) {
+                    Some(found) => found.0 = Some(pathname.clone()),
+                    None => {
+                        if let Some(buffer) = pending.remove(&guid) {
+                            if selected(&guid, Some(&pathname)) {
+                                found.insert(guid.clone(), (Some(pathname.clone()), buffer));
+                            }
+                        }
+                    }
+                }
+
+                pathnames.insert(guid, pathname);
+            } else if entry_name == file_name {
+                let pathname = pathnames.get(&guid);
+                let known = guids.contains(&guid) || pathname.is_some();
+
+                if known && !selected(&guid, pathname) {
+                    continue;
+                }
+
+                let mut buffer = Vec::new();
+                file.read_to_end(&mut buffer)?;
+
+                if known {
+                    found.insert(guid, (pathname.cloned(), buffer));
+                } else if selection.glob.is_some() || selection.regex.is_some() {
+                    pending.insert(guid, buffer);
+                }
+            }
+        }
+    }
+
+    let mut missing = guids
+        .iter()
+        .filter(|guid| !found.contains_key(*guid))
+        .map(|guid| format!("{}/{}", guid, file_name))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        missing.sort();
+        return Err(Error::MissingGuid(missing.join(", ")));
+    }
+
+    let metas = extract_metas(
+        &package,
+        found
+            .iter()
+            .map(|(guid, (pathname, _))| (guid.as_str(), pathname)),
+        options,
+    )?;
+
+    if to_dir {
+        let output_dir = options.output_file.as_ref().unwrap();
+
+        for (guid, (pathname, buffer)) in found {
+            let output = if options.json {
+                let documents = extract_documents(&buffer, &guid, options.meta, scripts.as_ref())?;
+                format!("{}\n", serde_json_to_string(&documents, options.pretty)?).into_bytes()
+            } else {
+                extract_convert(buffer, &pathname, metas.get(&guid), options)?
+            };
+            let output = extract_encode(output, options);
+
+            // Keep the pathname's folders so equal file names don't collide
+            let file_dir = match &pathname {
+                Some(pathname) => package::safe_join(output_dir, pathname)?
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_else(|| output_dir.to_owned()),
+                None => output_dir.to_owned(),
+            };
+            let output_file = file_dir.join(extract_file_name(&guid, &pathname, options));
+            check_overwrite(&output_file, options.force)?;
+
+            if let Some(parent) = output_file.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(output_file, output)?;
+        }
+
+        return Ok(());
+    }
+
+    // guid -> parsed documents with --json, base64 data otherwise
+    let mut output = BTreeMap::<String, serde_yaml::Value>::new();
+    for (guid, (pathname, buffer)) in found {
+        let value = if options.json {
+            extract_documents(&buffer, &guid, options.meta, scripts.as_ref())?
+        } else {
+            let data = extract_convert(buffer, &pathname, metas.get(&guid), options)?;
+            serde_yaml::Value::String(general_purpose::STANDARD.encode(data))
+        };
+        output.insert(guid, value);
+    }
+
+    let output = serde_json_to_string(&output, options.pretty)?;
+    match &options.output_file {
+        None => writeln!(std::io::stdout(), "{}", output)?,
+        Some(output_file) => {
+            if let Some(parent) = output_file.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(output_file, output)?;
+        }
+    }
+
+    Ok(())
+}
+
+//----------------------------------------
+
+fn read_guid_list(path: &Path) -> Result<Vec<String>> {
+    let mut list = String::new();
+    if path == Path::new("-") {
+        std::io::stdin().read_to_string(&mut list)?;
+    } else {
+        list = std::fs::read_to_string(path)?;
+    }
+
+    Ok(list
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_owned())
+        .collect())
+}
+
+//----------------------------------------
+
+fn extract_documents(
+    buffer: &[u8],
     guid: &str,
-    _output_file: &Option<PathBuf>,
     meta: bool,
-    json: bool,
-    pretty: bool,
-    fbx2gltf: bool,
-    base64: bool,
-) {
-    let looking_for = format!("{}/{}", guid, if meta { "asset.meta" } else { "asset" });
-
-    for file in package::Package::new(package_file)
-        .unwrap()
-        .open()
-        .unwrap()
-        .entries()
-    {
-        let mut file = file.unwrap();
+    scripts: Option<&ScriptIndex>,
+) -> Result<serde_yaml::Value> {
+    let yaml = asset_str(buffer, guid)?;
+    if meta {
+        asset_meta_value(yaml)
+    } else {
+        let mut documents = unity_yaml::documents_to_value(&unity_yaml::parse(yaml)?);
+        if let Some(scripts) = scripts {
+            scripts.annotate(&mut documents);
+        }
+        Ok(documents)
+    }
+}
+
+//----------------------------------------
+
+fn extract_scripts(
+    package: &package::Package,
+    options: &ExtractOptions,
+) -> Result<Option<ScriptIndex>> {
+    if options.resolve_scripts && options.json && !options.meta {
+        Ok(Some(ScriptIndex::build(package)?))
+    } else {
+        Ok(None)
+    }
+}
+
+//----------------------------------------
+
+/// The asset is a texture source `convert_texture` applies to
+fn converts_texture(pathname: &Option<String>, options: &ExtractOptions) -> bool {
+    options.convert_texture.is_some()
+        && !options.meta
+        && !options.preview
+        && !options.json
+        && pathname
+            .as_ref()
+            .is_some_and(|pathname| texture::is_texture(pathname))
+}
+
+//----------------------------------------
+
+/// `<guid>/asset.meta` of the textures `convert_texture` applies to
+fn extract_metas<'a>(
+    package: &package::Package,
+    assets: impl IntoIterator<Item = (&'a str, &'a Option<String>)>,
+    options: &ExtractOptions,
+) -> Result<HashMap<String, Vec<u8>>> {
+    let wanted = assets
+        .into_iter()
+        .filter(|(_, pathname)| converts_texture(pathname, options))
+        .map(|(guid, _)| guid.to_owned())
+        .collect::<HashSet<String>>();
+    if wanted.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    if let Some(index) = PackageIndex::load_sidecar(package)? {
+        let wanted = wanted
+            .into_iter()
+            .filter(|guid| {
+                index
+                    .guids
+                    .get(guid)
+                    .is_some_and(|entry| entry.files.contains_key("asset.meta"))
+            })
+            .map(|guid| (guid, "asset.meta".to_owned()))
+            .collect::<Vec<(String, String)>>();
+        let metas = index.read_many(package, &wanted)?;
+        return Ok(wanted
+            .into_iter()
+            .map(|(guid, _)| guid)
+            .zip(metas)
+            .collect());
+    }
+
+    let mut metas = HashMap::new();
+    for file in package.clone().open()?.entries()? {
+        let mut file = file?;
+
+        let guid = file.guid()?;
+        if wanted.contains(&guid) && file.file_name()? == "asset.meta" {
+            let mut buffer = Vec::new();
+            file.read_to_end(&mut buffer)?;
+            metas.insert(guid, buffer);
+
+            if metas.len() == wanted.len() {
+                break;
+            }
+        }
+    }
+
+    Ok(metas)
+}
+
+//----------------------------------------
+
+fn extract_convert(
+    buffer: Vec<u8>,
+    pathname: &Option<String>,
+    meta: Option<&Vec<u8>>,
+    options: &ExtractOptions,
+) -> Result<Vec<u8>> {
+    // Thumbnails are PNG already
+    if options.preview {
+        return Ok(buffer);
+    }
+
+    if let (Some(format), true) = (options.convert_texture, converts_texture(pathname, options)) {
+        let settings = match meta {
+            Some(meta) => {
+                let meta =
+                    std::str::from_utf8(meta).map_err(|err| Error::BadMeta(err.to_string()))?;
+                ImporterSettings::from_meta(&asset_meta_value(meta)?)
+            }
+            None => ImporterSettings::default(),
+        };
+        return texture::convert(&buffer, pathname.as_deref().unwrap(), &settings, format);
+    }
+
+    // An explicit converter path wins over the built-in one
+    if options.fbx2gltf && cfg!(feature = "native-fbx") && options.converter.program.is_none() {
+        convert_fbx_native(&buffer)
+    } else if options.fbx2gltf {
+        options.converter.convert(&buffer)
+    } else {
+        Ok(buffer)
+    }
+}
+
+//----------------------------------------
+
+fn extract_encode(output: Vec<u8>, options: &ExtractOptions) -> Vec<u8> {
+    if options.base64 && !options.json {
+        general_purpose::STANDARD.encode(output).into_bytes()
+    } else {
+        output
+    }
+}
+
+//----------------------------------------
+
+fn find_entry(
+    package: package::Package,
+    looking_for: &str,
+    looking_for_pathname: &str,
+    with_pathname: bool,
+) -> Result<(Option<Vec<u8>>, Option<String>)> {
+    let mut buffer = None;
+    let mut pathname = None;
 
-        let file_path = file.path().unwrap().to_str().unwrap().to_owned();
-        let size = file.size().unwrap();
+    for file in package.open()?.entries()? {
+        let mut file = file?;
+
+        let file_path = file.path()?.to_string_lossy().to_string();
 
         if file_path == looking_for {
-            let mut buffer = Vec::with_capacity(size);
-            file.read_to_end(&mut buffer).unwrap();
-
-            if json {
-                let yaml = std::str::from_utf8(&buffer).unwrap();
-                if meta {
-                    let yaml = unitypackage_util::asset_meta_yaml_cleanup(&yaml);
-                    print!("{}", yaml_to_json::<serde_json::Value>(&yaml, pretty));
-                } else {
-                    let yaml = unitypackage_util::asset_yaml_cleanup(&yaml);
-                    print!("{}", yaml_to_json::<serde_json::Value>(&yaml, pretty));
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            buffer = Some(data);
+        } else if with_pathname && file_path == looking_for_pathname {
+            let mut s = String::new();
+            file.read_to_string(&mut s)?;
+            pathname = Some(s.split("\n").next().unwrap().to_owned());
+        }
+
+        if buffer.is_some() && (!with_pathname || pathname.is_some()) {
+            break;
+        }
+    }
+
+    Ok((buffer, pathname))
+}
+
+//----------------------------------------
+
+/// `<guid>/asset`, through the sidecar index when there is one
+fn read_asset(package: &package::Package, guid: &str) -> Result<Vec<u8>> {
+    if let Some(index) = PackageIndex::load_sidecar(package)? {
+        return index.read(package, guid, "asset");
+    }
+
+    let looking_for = format!("{}/asset", guid);
+    find_entry(package.clone(), &looking_for, "", false)?
+        .0
+        .ok_or(Error::MissingGuid(looking_for))
+}
+
+//----------------------------------------
+
+fn extract_file_name(guid: &str, pathname: &Option<String>, options: &ExtractOptions) -> PathBuf {
+    let mut file_name = pathname
+        .as_ref()
+        .and_then(|pathname| Path::new(pathname).file_name())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(guid));
+
+    if options.meta {
+        file_name.as_mut_os_string().push(".meta");
+    } else if options.preview {
+        file_name.as_mut_os_string().push(".png");
+        return file_name;
+    }
+
+    if options.json {
+        file_name.as_mut_os_string().push(".json");
+    } else if let (Some(format), true) =
+        (options.convert_texture, converts_texture(pathname, options))
+    {
+        file_name.set_extension(format.extension());
+    } else if options.fbx2gltf {
+        file_name.set_extension("glb");
+    }
+
+    file_name
+}
+
+//----------------------------------------
+
+fn check_overwrite(output_file: &Path, force: bool) -> Result<()> {
+    if output_file.exists() && !force {
+        return Err(Error::OutputExists(output_file.to_owned()));
+    }
+    Ok(())
+}
+
+//----------------------------------------
+
+#[cfg(feature = "native-fbx")]
+fn convert_fbx_native(buf: &[u8]) -> Result<Vec<u8>> {
+    unitypackage_util::glb::fbx_to_glb(buf)
+}
+
+#[cfg(not(feature = "native-fbx"))]
+fn convert_fbx_native(buf: &[u8]) -> Result<Vec<u8>> {
+    Fbx2Gltf::default().convert(buf)
+}
+
+//----------------------------------------
+
+pub fn package_unpack(
+    package_file: &str,
+    limits: Option<ReadLimits>,
+    output_dir: &Path,
+    dir: &Option<String>,
+    dry_run: bool,
+    force: bool,
+) -> Result<()> {
+    let dir = dir.as_deref().map(|dir| dir.trim_end_matches('/'));
+    let mut pathnames = HashMap::<String, String>::new();
+    // Entries seen before their guid/pathname, written once it shows up
+    let mut pending = HashMap::<String, Vec<(String, Vec<u8>)>>::new();
+    let mut written = Vec::new();
+
+    let mut unpack = |pathname: &str, file_name: &str, buffer: &[u8]| -> Result<()> {
+        if let Some(dir) = dir {
+            if pathname != dir && !pathname.starts_with(&format!("{}/", dir)) {
+                return Ok(());
+            }
+        }
+
+        let target = match file_name {
+            "asset.meta" => package::safe_join(output_dir, &format!("{}.meta", pathname))?,
+            _ => package::safe_join(output_dir, pathname)?,
+        };
+
+        if !dry_run {
+            check_overwrite(&target, force)?;
+            if file_name == "asset.meta" && unitypackage_util::asset_meta_is_folder(buffer) {
+                std::fs::create_dir_all(package::safe_join(output_dir, pathname)?)?;
+            }
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&target, buffer)?;
+        }
+
+        written.push(target);
+        Ok(())
+    };
+
+    for file in open_package(package_file, limits)?.open()?.entries()? {
+        let mut file = file?;
+
+        let file_path = file.path()?.to_string_lossy().to_string();
+
+        let guid = file.guid()?;
+        if guid.len() < 32 {
+            continue;
+        }
+
+        let file_name = match file_path.strip_prefix(&format!("{}/", guid)) {
+            Some(name @ ("pathname" | "asset" | "asset.meta")) => name.to_owned(),
+            _ => continue,
+        };
+
+        if file_name == "pathname" {
+            let mut pathname = String::new();
+            file.read_to_string(&mut pathname)?;
+            let pathname = pathname.split("\n").next().unwrap().to_owned();
+
+            for (file_name, buffer) in pending.remove(&guid).unwrap_or_default() {
+                unpack(&pathname, &file_name, &buffer)?;
+            }
+            pathnames.insert(guid, pathname);
+            continue;
+        }
+
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+
+        match pathnames.get(&guid) {
+            Some(pathname) => unpack(pathname, &file_name, &buffer)?,
+            None => pending.entry(guid).or_default().push((file_name, buffer)),
+        }
+    }
+
+    for guid in pending.keys() {
+        std::io::stderr()
+            .write_all(format!("Skipping {}, no pathname in package\n", guid).as_bytes())?;
+    }
+
+    if dry_run {
+        written.sort();
+        for target in written {
+            writeln!(std::io::stdout(), "{}", target.display())?;
+        }
+    }
+
+    Ok(())
+}
+
+//----------------------------------------
+
+/// Write each preview.png to `<output_dir>/<pathname>.png`
+pub fn package_thumbnails(
+    package_file: &str,
+    limits: Option<ReadLimits>,
+    output_dir: &Path,
+    dir: &Option<String>,
+    dry_run: bool,
+) -> Result<()> {
+    let mut pathnames = HashMap::<String, String>::new();
+    // Previews seen before their guid/pathname
+    let mut pending = HashMap::<String, Vec<u8>>::new();
+    let mut written = Vec::new();
+
+    let mut write = |pathname: &str, buffer: &[u8]| -> Result<()> {
+        if let Some(dir) = dir {
+            if pathname != dir && !pathname.starts_with(&format!("{}/", dir)) {
+                return Ok(());
+            }
+        }
+
+        let target = package::safe_join(output_dir, &format!("{}.png", pathname))?;
+        if !dry_run {
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&target, buffer)?;
+        }
+
+        written.push(target);
+        Ok(())
+    };
+
+    for file in open_package(package_file, limits)?.open()?.entries()? {
+        let mut file = file?;
+
+        let guid = file.guid()?;
+        if guid.len() < 32 {
+            continue;
+        }
+
+        match file.file_name()?.as_str() {
+            "pathname" => {
+                let mut pathname = String::new();
+                file.read_to_string(&mut pathname)?;
+                let pathname = pathname.split("\n").next().unwrap().to_owned();
+
+                if let Some(buffer) = pending.remove(&guid) {
+                    write(&pathname, &buffer)?;
                 }
-            } else {
-                if fbx2gltf {
-                    convert_fbx2gltf(&mut buffer, base64).unwrap();
-                } else if base64 {
-                    print!("{}", general_purpose::STANDARD.encode(buffer));
-                } else {
-                    std::io::stdout().write_all(&buffer).unwrap();
+                pathnames.insert(guid, pathname);
+            }
+            "preview.png" => {
+                let mut buffer = Vec::new();
+                file.read_to_end(&mut buffer)?;
+
+                match pathnames.get(&guid) {
+                    Some(pathname) => write(pathname, &buffer)?,
+                    None => {
+                        pending.insert(guid, buffer);
+                    }
                 }
             }
+            _ => (),
+        }
+    }
+
+    for guid in pending.keys() {
+        std::io::stderr()
+            .write_all(format!("Skipping {}, no pathname in package\n", guid).as_bytes())?;
+    }
 
-            return;
+    if dry_run {
+        written.sort();
+        for target in written {
+            writeln!(std::io::stdout(), "{}", target.display())?;
         }
     }
 
-    std::io::stderr()
-        .write_all(format!("Could not find {} in package\n", looking_for).as_bytes())
-        .unwrap();
-    std::process::exit(exitcode::NOINPUT);
+    Ok(())
+}
+
+//----------------------------------------
+
+pub fn package_index(package_file: &str, limits: Option<ReadLimits>) -> Result<()> {
+    let package = open_package(package_file, limits)?;
+    if package.in_memory() {
+        return Err(Error::NotAPackage(
+            "index is saved next to the package, stdin has nowhere to put it".to_owned(),
+        ));
+    }
+    let index = PackageIndex::build(&package)?;
+
+    let sidecar = PackageIndex::sidecar_path(package.path());
+    index.save(&sidecar)?;
+
+    writeln!(std::io::stdout(), "{}", sidecar.display())?;
+    Ok(())
 }
 
 //----------------------------------------
 
-pub fn convert_fbx2gltf(buf: &mut Vec<u8>, base64: bool) -> Result<(), std::io::Error> {
-    let program = if cfg!(windows) {
-        "FBX2glTF.exe"
+pub fn package_deps(
+    package_file: &str,
+    limits: Option<ReadLimits>,
+    guid: &Option<String>,
+    dot: bool,
+    pretty: bool,
+) -> Result<()> {
+    let mut graph = DependencyGraph::build(&open_package(package_file, limits)?)?;
+
+    if let Some(guid) = guid {
+        if !graph.assets.contains_key(guid) {
+            return Err(Error::MissingGuid(guid.clone()));
+        }
+        graph = graph.subgraph(guid);
+    }
+
+    if dot {
+        write!(std::io::stdout(), "{}", graph.to_dot())?;
     } else {
-        "./FBX2glTF"
-    };
+        writeln!(
+            std::io::stdout(),
+            "{}",
+            serde_json_to_string(&graph.assets, pretty)?
+        )?;
+    }
+    Ok(())
+}
 
-    let process = Command::new(program)
-        .arg("-IO")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()?;
+//----------------------------------------
 
-    process.stdin.unwrap().write_all(buf)?;
+pub fn package_check(package_file: &str, limits: Option<ReadLimits>, pretty: bool) -> Result<()> {
+    let broken = deps::check_package(&open_package(package_file, limits)?)?;
 
-    let mut buffer = Vec::new();
-    process.stdout.unwrap().read_to_end(&mut buffer)?;
+    writeln!(
+        std::io::stdout(),
+        "{}",
+        serde_json_to_string(&broken, pretty)?
+    )?;
 
-    if base64 {
-        print!("{}", general_purpose::STANDARD.encode(buffer));
-        return Ok(());
+    match broken.len() {
+        0 => Ok(()),
+        count => Err(Error::CheckFailed(format!("{} broken references", count))),
+    }
+}
+
+//----------------------------------------
+
+pub fn package_validate(
+    package_file: &str,
+    limits: Option<ReadLimits>,
+    pretty: bool,
+) -> Result<()> {
+    let report = validate::validate_package(&open_package(package_file, limits)?)?;
+
+    writeln!(
+        std::io::stdout(),
+        "{}",
+        serde_json_to_string(&report, pretty)?
+    )?;
+
+    match report.errors {
+        0 => Ok(()),
+        count => Err(Error::CheckFailed(format!("{} validation errors", count))),
+    }
+}
+
+//----------------------------------------
+
+pub fn package_classes(package_file: &str, limits: Option<ReadLimits>, pretty: bool) -> Result<()> {
+    let mut classes = BTreeMap::<i32, ClassCount>::new();
+
+    for file in open_package(package_file, limits)?.open()?.entries()? {
+        let mut file = file?;
+
+        if file.file_name()? != "asset" {
+            continue;
+        }
+
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+
+        if !buffer.starts_with(b"%YAML") {
+            continue;
+        }
+
+        let yaml = asset_str(&buffer, &file.guid()?)?;
+        for document in unity_yaml::parse_recover(yaml).0 {
+            let class = classes.entry(document.class_id).or_default();
+            class.name = class_id::class_name(document.class_id);
+            class.count += 1;
+            class.mismatch |= !class_id::type_matches(document.class_id, &document.type_name);
+            *class.types.entry(document.type_name).or_default() += 1;
+        }
     }
 
-    std::io::stdout().write_all(&buffer)?;
+    writeln!(
+        std::io::stdout(),
+        "{}",
+        serde_json_to_string(&classes, pretty)?
+    )?;
     Ok(())
 }
 
 //----------------------------------------
 
-pub fn xx_hash(text: &str) {
-    print!("{}", xxh64::xxh64(text.as_bytes(), 0) as i64)
+/// With `flatten`, prefab instances whose source prefab is in the package
+/// are replaced by the source's objects with their overrides applied
+pub fn package_hierarchy(
+    package_file: &str,
+    limits: Option<ReadLimits>,
+    guid: &str,
+    flatten: bool,
+    pretty: bool,
+) -> Result<()> {
+    let package = open_package(package_file, limits)?;
+    let buffer = read_asset(&package, guid)?;
+    let mut documents = unity_yaml::parse(asset_str(&buffer, guid)?)?;
+
+    if flatten {
+        let flattened = PrefabSources::from_package(&package)?.flatten(&documents);
+        for unresolved in flattened.unresolved {
+            std::io::stderr().write_all(
+                format!(
+                    "Keeping PrefabInstance {}, {}\n",
+                    unresolved.file_id, unresolved.reason
+                )
+                .as_bytes(),
+            )?;
+        }
+        documents = flattened.documents;
+    }
+
+    writeln!(
+        std::io::stdout(),
+        "{}",
+        serde_json_to_string(&hierarchy::build(&documents), pretty)?
+    )?;
+    Ok(())
+}
+
+//----------------------------------------
+
+pub fn package_pack(package_file: &str, project: &Path, dir: &str) -> Result<()> {
+    let mut writer = PackageWriter::create(Path::new(package_file))?;
+
+    for path in writer.add_project(project, dir)? {
+        std::io::stderr()
+            .write_all(format!("Skipping {}, no .meta file\n", path.display()).as_bytes())?;
+    }
+
+    writer.finish()?;
+    Ok(())
+}
+
+//----------------------------------------
+
+/// Merge `inputs` into a new package at `package_file`
+pub fn package_merge(
+    package_file: &str,
+    limits: Option<ReadLimits>,
+    inputs: &[PathBuf],
+    guids: GuidConflict,
+    pathnames: PathnameConflict,
+    force: bool,
+) -> Result<()> {
+    let output_file = Path::new(package_file);
+    check_overwrite(output_file, force)?;
+    check_stdin_once(&inputs.iter().map(PathBuf::as_path).collect::<Vec<&Path>>())?;
+
+    let packages = inputs
+        .iter()
+        .map(|input| open_package(&input.to_string_lossy(), limits))
+        .collect::<Result<Vec<package::Package>>>()?;
+
+    // Writing would truncate an input before it is read
+    let output = output_file.canonicalize().ok();
+    if let Some(package) = packages
+        .iter()
+        .find(|package| output.is_some() && package.path().canonicalize().ok() == output)
+    {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "{} is both an input and the output",
+                package.path().display()
+            ),
+        )));
+    }
+
+    let mut writer = PackageWriter::create(output_file)?;
+    let report = merge::merge_packages(&packages, &mut writer, guids, pathnames)?;
+    writer.finish()?;
+
+    let mut stderr = std::io::stderr();
+    for duplicate in &report.duplicates {
+        writeln!(
+            stderr,
+            "Duplicate {} {}, keeping {}",
+            duplicate.guid, duplicate.pathname, duplicate.kept
+        )?;
+    }
+    for renamed in &report.renamed {
+        writeln!(
+            stderr,
+            "Renamed {} {} to {}",
+            renamed.guid, renamed.from, renamed.to
+        )?;
+    }
+    for folder in &report.merged_folders {
+        writeln!(stderr, "Merged folder {}", folder)?;
+    }
+    writeln!(stderr, "Merged {} assets", report.assets)?;
+
+    Ok(())
+}
+
+//----------------------------------------
+
+pub fn xx_hash(text: &str) -> Result<()> {
+    write!(
+        std::io::stdout(),
+        "{}",
+        xxh64::xxh64(text.as_bytes(), 0) as i64
+    )?;
+    Ok(())
 }
 
 //----------------------------------------
@@ -0,0 +1,332 @@
+//----------------------------------------
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::error::Result;
+use crate::package::Package;
+use crate::unity_yaml::{self, serialize_file_id, UnityDocument};
+
+//----------------------------------------
+
+/// GUIDs of the resources built into Unity, never present in a package
+pub const BUILTIN_GUIDS: &[&str] = &[
+    // unity_builtin_extra
+    "0000000000000000e000000000000000",
+    // unity default resources
+    "0000000000000000f000000000000000",
+    // unity editor resources
+    "0000000000000000d000000000000000",
+];
+
+//----------------------------------------
+
+/// A `{fileID: X, guid: Y, type: Z}` reference found in a document.
+/// fileIDs serialize as strings, like `dump`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Reference {
+    /// fileID of the document the reference appears in
+    #[serde(serialize_with = "serialize_file_id")]
+    pub document: i64,
+    /// Field path, "MonoBehaviour.m_Script", "Transform.m_Children[0]", etc
+    pub path: String,
+    #[serde(serialize_with = "serialize_file_id")]
+    pub file_id: i64,
+    /// None for references to another document of the same asset
+    pub guid: Option<String>,
+    pub r#type: Option<i64>,
+}
+
+//----------------------------------------
+
+/// guid -> guids referenced by its YAML asset
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DependencyGraph {
+    pub assets: BTreeMap<String, AssetDependencies>,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AssetDependencies {
+    pub pathname: Option<String>,
+    /// Referenced guid -> every place it is referenced from
+    pub dependencies: BTreeMap<String, Vec<Reference>>,
+}
+
+/// A reference `check` could not resolve
+#[derive(Debug, Clone, Serialize)]
+pub struct BrokenReference {
+    /// Asset the reference appears in
+    pub guid: String,
+    pub pathname: Option<String>,
+    /// "missing_guid" or "missing_file_id"
+    pub problem: &'static str,
+    pub reference: Reference,
+}
+
+//----------------------------------------
+
+pub fn is_builtin_guid(guid: &str) -> bool {
+    BUILTIN_GUIDS.contains(&guid)
+}
+
+//----------------------------------------
+
+/// Every non-null fileID reference in the document, local or external
+pub fn references(document: &UnityDocument) -> Vec<Reference> {
+    let mut found = Vec::new();
+    collect_references(
+        &document.body,
+        &document.type_name,
+        document.file_id,
+        &mut found,
+    );
+    found
+}
+
+//----------------------------------------
+
+fn collect_references(value: &Value, path: &str, document: i64, found: &mut Vec<Reference>) {
+    match value {
+        Value::Mapping(mapping) => {
+            if let Some(file_id) = mapping.get("fileID").and_then(Value::as_i64) {
+                if file_id != 0 {
+                    found.push(Reference {
+                        document,
+                        path: path.to_owned(),
+                        file_id,
+                        guid: mapping
+                            .get("guid")
+                            .and_then(Value::as_str)
+                            .filter(|guid| !guid.is_empty())
+                            .map(|guid| guid.to_owned()),
+                        r#type: mapping.get("type").and_then(Value::as_i64),
+                    });
+                }
+                return;
+            }
+
+            for (key, value) in mapping {
+                let key = match key {
+                    Value::String(key) => key.clone(),
+                    key => serde_yaml::to_string(key)
+                        .unwrap_or_default()
+                        .trim_end()
+                        .to_owned(),
+                };
+                collect_references(value, &format!("{}.{}", path, key), document, found);
+            }
+        }
+        Value::Sequence(sequence) => {
+            for (i, value) in sequence.iter().enumerate() {
+                collect_references(value, &format!("{}[{}]", path, i), document, found);
+            }
+        }
+        _ => (),
+    }
+}
+
+//----------------------------------------
+
+impl DependencyGraph {
+    /// Walk every YAML asset in the package. Documents that fail to parse
+    /// are skipped, `dump --recover` reports them.
+    pub fn build(package: &Package) -> Result<DependencyGraph> {
+        let mut graph = DependencyGraph::default();
+
+        for file in package.clone().open()?.entries()? {
+            let mut file = file?;
+
+            let guid = file.guid()?;
+            if guid.len() < 32 {
+                continue;
+            }
+
+            let file_name = file.file_name()?;
+            if file_name != "pathname" && file_name != "asset" {
+                continue;
+            }
+
+            let mut buffer = Vec::new();
+            file.read_to_end(&mut buffer)?;
+
+            let entry = graph.assets.entry(guid.clone()).or_default();
+
+            if file_name == "pathname" {
+                let pathname = String::from_utf8_lossy(&buffer);
+                entry.pathname = Some(pathname.split('\n').next().unwrap_or("").to_owned());
+            } else if buffer.starts_with(b"%YAML") {
+                if let Ok(yaml) = std::str::from_utf8(&buffer) {
+                    entry.add_documents(&unity_yaml::parse_recover(yaml).0);
+                }
+            }
+        }
+
+        Ok(graph)
+    }
+
+    /// `guid` and every asset it depends on, directly or not
+    pub fn reachable(&self, guid: &str) -> BTreeSet<String> {
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![guid.to_owned()];
+
+        while let Some(guid) = pending.pop() {
+            if !seen.insert(guid.clone()) {
+                continue;
+            }
+            if let Some(asset) = self.assets.get(&guid) {
+                pending.extend(asset.dependencies.keys().cloned());
+            }
+        }
+
+        seen
+    }
+
+    /// The part of the graph reachable from `guid`
+    pub fn subgraph(&self, guid: &str) -> DependencyGraph {
+        let reachable = self.reachable(guid);
+        DependencyGraph {
+            assets: self
+                .assets
+                .iter()
+                .filter(|(guid, _)| reachable.contains(*guid))
+                .map(|(guid, asset)| (guid.clone(), asset.clone()))
+                .collect(),
+        }
+    }
+
+    /// Graphviz DOT, nodes labelled by pathname, edges by field path.
+    /// GUIDs not in the package are drawn dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph dependencies {\n    rankdir=LR;\n");
+        let mut external = BTreeSet::new();
+
+        for (guid, asset) in &self.assets {
+            let label = asset.pathname.as_deref().unwrap_or(guid);
+            let _ = writeln!(dot, "    \"{}\" [label={}];", guid, dot_string(label));
+        }
+
+        for (guid, asset) in &self.assets {
+            for (dependency, references) in &asset.dependencies {
+                if !self.assets.contains_key(dependency) {
+                    external.insert(dependency);
+                }
+
+                let paths = references
+                    .iter()
+                    .map(|reference| reference.path.as_str())
+                    .collect::<BTreeSet<&str>>();
+                let label = paths.into_iter().collect::<Vec<&str>>().join("\n");
+                let _ = writeln!(
+                    dot,
+                    "    \"{}\" -> \"{}\" [label={}];",
+                    guid,
+                    dependency,
+                    dot_string(&label)
+                );
+            }
+        }
+
+        for guid in external {
+            let _ = writeln!(dot, "    \"{}\" [style=dashed];", guid);
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+//----------------------------------------
+
+impl AssetDependencies {
+    pub fn add_documents(&mut self, documents: &[UnityDocument]) {
+        for reference in documents.iter().flat_map(references) {
+            if let Some(guid) = reference.guid.clone() {
+                self.dependencies.entry(guid).or_default().push(reference);
+            }
+        }
+    }
+}
+
+//----------------------------------------
+
+/// References to GUIDs that are neither in the package nor built in, and
+/// local references to fileIDs that are not documents of the same asset
+pub fn check_package(package: &Package) -> Result<Vec<BrokenReference>> {
+    let mut guids = BTreeSet::new();
+    let mut pathnames = BTreeMap::new();
+    let mut assets = Vec::new();
+
+    for file in package.clone().open()?.entries()? {
+        let mut file = file?;
+
+        let guid = file.guid()?;
+        if guid.len() < 32 {
+            continue;
+        }
+        guids.insert(guid.clone());
+
+        let file_name = file.file_name()?;
+        if file_name != "pathname" && file_name != "asset" {
+            continue;
+        }
+
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+
+        if file_name == "pathname" {
+            let pathname = String::from_utf8_lossy(&buffer);
+            let pathname = pathname.split('\n').next().unwrap_or("").to_owned();
+            pathnames.insert(guid, pathname);
+        } else if buffer.starts_with(b"%YAML") {
+            if let Ok(yaml) = std::str::from_utf8(&buffer) {
+                assets.push((guid, unity_yaml::parse_recover(yaml).0));
+            }
+        }
+    }
+
+    let mut broken = Vec::new();
+
+    for (guid, documents) in assets {
+        let file_ids = documents
+            .iter()
+            .map(|document| document.file_id)
+            .collect::<BTreeSet<i64>>();
+
+        for reference in documents.iter().flat_map(references) {
+            let problem = match &reference.guid {
+                Some(target) if !guids.contains(target) && !is_builtin_guid(target) => {
+                    "missing_guid"
+                }
+                None if !file_ids.contains(&reference.file_id) => "missing_file_id",
+                _ => continue,
+            };
+
+            broken.push(BrokenReference {
+                guid: guid.clone(),
+                pathname: pathnames.get(&guid).cloned(),
+                problem,
+                reference,
+            });
+        }
+    }
+
+    Ok(broken)
+}
+
+//----------------------------------------
+
+fn dot_string(s: &str) -> String {
+    format!(
+        "\"{}\"",
+        s.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+//----------------------------------------
@@ -0,0 +1,307 @@
+//----------------------------------------
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use serde_yaml::Value;
+use xxhash_rust::xxh64;
+
+use crate::error::Result;
+use crate::package::Package;
+use crate::unity_yaml::{self, serialize_file_id, UnityDocument};
+
+//----------------------------------------
+
+/// Differences between an old and a new package, matched by GUID
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PackageDiff {
+    /// Only in the new package
+    pub added: Vec<AssetRef>,
+    /// Only in the old package
+    pub removed: Vec<AssetRef>,
+    /// Same GUID, different pathname
+    pub renamed: Vec<Renamed>,
+    /// Same GUID, different asset data
+    pub content_changed: Vec<ContentChange>,
+    /// Same GUID, different asset.meta
+    pub meta_changed: Vec<AssetRef>,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AssetRef {
+    pub guid: String,
+    pub pathname: Option<String>,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Renamed {
+    pub guid: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContentChange {
+    pub guid: String,
+    /// Pathname in the new package
+    pub pathname: Option<String>,
+    pub old_size: u64,
+    pub new_size: u64,
+    /// Per document changes, with the structural diff of YAML assets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub documents: Option<Vec<DocumentChange>>,
+}
+
+//----------------------------------------
+
+/// A document added, removed, or changed, matched by fileID
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentChange {
+    #[serde(serialize_with = "serialize_file_id")]
+    pub file_id: i64,
+    pub r#type: String,
+    /// "added", "removed", or "changed"
+    pub change: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub properties: Vec<PropertyChange>,
+}
+
+//----------------------------------------
+
+/// A changed field, None on the side it is missing from
+#[derive(Debug, Clone, Serialize)]
+pub struct PropertyChange {
+    /// "m_LocalPosition.x", "m_Children[2]", etc
+    pub path: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+//----------------------------------------
+
+/// What the diff needs of each GUID
+#[derive(Debug, Default)]
+struct Snapshot {
+    pathname: Option<String>,
+    asset: Option<(u64, u64)>,
+    meta: Option<u64>,
+    /// Kept for the structural diff
+    yaml: Option<String>,
+}
+
+//----------------------------------------
+
+/// Compare by GUID. With `structural`, content changes of YAML assets list
+/// the documents and fields that differ.
+pub fn diff_packages(old: &Package, new: &Package, structural: bool) -> Result<PackageDiff> {
+    let old = snapshot(old, structural)?;
+    let new = snapshot(new, structural)?;
+
+    let mut diff = PackageDiff::default();
+
+    for (guid, before) in &old {
+        if !new.contains_key(guid) {
+            diff.removed.push(AssetRef {
+                guid: guid.clone(),
+                pathname: before.pathname.clone(),
+            });
+        }
+    }
+
+    for (guid, after) in &new {
+        let before = match old.get(guid) {
+            Some(before) => before,
+            None => {
+                diff.added.push(AssetRef {
+                    guid: guid.clone(),
+                    pathname: after.pathname.clone(),
+                });
+                continue;
+            }
+        };
+
+        if before.pathname != after.pathname {
+            diff.renamed.push(Renamed {
+                guid: guid.clone(),
+                from: before.pathname.clone(),
+                to: after.pathname.clone(),
+            });
+        }
+
+        if before.asset.map(|(hash, _)| hash) != after.asset.map(|(hash, _)| hash) {
+            let documents = match (&before.yaml, &after.yaml) {
+                (Some(old), Some(new)) => Some(diff_documents(
+                    &unity_yaml::parse_recover(old).0,
+                    &unity_yaml::parse_recover(new).0,
+                )),
+                _ => None,
+            };
+
+            diff.content_changed.push(ContentChange {
+                guid: guid.clone(),
+                pathname: after.pathname.clone(),
+                old_size: before.asset.map(|(_, size)| size).unwrap_or(0),
+                new_size: after.asset.map(|(_, size)| size).unwrap_or(0),
+                documents,
+            });
+        }
+
+        if before.meta != after.meta {
+            diff.meta_changed.push(AssetRef {
+                guid: guid.clone(),
+                pathname: after.pathname.clone(),
+            });
+        }
+    }
+
+    Ok(diff)
+}
+
+//----------------------------------------
+
+fn snapshot(package: &Package, keep_yaml: bool) -> Result<BTreeMap<String, Snapshot>> {
+    let mut snapshots = BTreeMap::<String, Snapshot>::new();
+
+    for file in package.clone().open()?.entries()? {
+        let mut file = file?;
+
+        let guid = file.guid()?;
+        if guid.len() < 32 {
+            continue;
+        }
+
+        let file_name = file.file_name()?;
+        if !matches!(file_name.as_str(), "pathname" | "asset" | "asset.meta") {
+            continue;
+        }
+
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        let snapshot = snapshots.entry(guid).or_default();
+
+        match file_name.as_str() {
+            "pathname" => {
+                let pathname = String::from_utf8_lossy(&buffer);
+                snapshot.pathname = Some(pathname.split('\n').next().unwrap_or("").to_owned());
+            }
+            "asset" => {
+                snapshot.asset = Some((xxh64::xxh64(&buffer, 0), buffer.len() as u64));
+                if keep_yaml && buffer.starts_with(b"%YAML") {
+                    snapshot.yaml = String::from_utf8(buffer).ok();
+                }
+            }
+            _ => snapshot.meta = Some(xxh64::xxh64(&buffer, 0)),
+        }
+    }
+
+    Ok(snapshots)
+}
+
+//----------------------------------------
+
+/// Documents matched by fileID, in the new asset's order, removed ones last
+pub fn diff_documents(old: &[UnityDocument], new: &[UnityDocument]) -> Vec<DocumentChange> {
+    let old_by_id = old
+        .iter()
+        .map(|document| (document.file_id, document))
+        .collect::<BTreeMap<i64, &UnityDocument>>();
+    let new_ids = new
+        .iter()
+        .map(|document| document.file_id)
+        .collect::<BTreeSet<i64>>();
+
+    let mut changes = Vec::new();
+
+    for document in new {
+        let change = match old_by_id.get(&document.file_id) {
+            None => DocumentChange {
+                file_id: document.file_id,
+                r#type: document.type_name.clone(),
+                change: "added",
+                properties: Vec::new(),
+            },
+            Some(before) => {
+                let mut properties = Vec::new();
+                diff_values(
+                    "",
+                    Some(&before.body),
+                    Some(&document.body),
+                    &mut properties,
+                );
+                if properties.is_empty() && before.type_name == document.type_name {
+                    continue;
+                }
+                DocumentChange {
+                    file_id: document.file_id,
+                    r#type: document.type_name.clone(),
+                    change: "changed",
+                    properties,
+                }
+            }
+        };
+        changes.push(change);
+    }
+
+    for document in old.iter().filter(|d| !new_ids.contains(&d.file_id)) {
+        changes.push(DocumentChange {
+            file_id: document.file_id,
+            r#type: document.type_name.clone(),
+            change: "removed",
+            properties: Vec::new(),
+        });
+    }
+
+    changes
+}
+
+//----------------------------------------
+
+/// Recurse into mappings by key and sequences by index, reporting the
+/// deepest paths that differ
+fn diff_values(
+    path: &str,
+    old: Option<&Value>,
+    new: Option<&Value>,
+    out: &mut Vec<PropertyChange>,
+) {
+    match (old, new) {
+        (Some(Value::Mapping(old)), Some(Value::Mapping(new))) => {
+            let mut keys = old.keys().collect::<Vec<&Value>>();
+            keys.extend(new.keys().filter(|key| !old.contains_key(*key)));
+
+            for key in keys {
+                let name = match key {
+                    Value::String(key) => key.clone(),
+                    key => serde_yaml::to_string(key)
+                        .map(|key| key.trim_end().to_owned())
+                        .unwrap_or_default(),
+                };
+                let path = if path.is_empty() {
+                    name
+                } else {
+                    format!("{}.{}", path, name)
+                };
+                diff_values(&path, old.get(key), new.get(key), out);
+            }
+        }
+        (Some(Value::Sequence(old)), Some(Value::Sequence(new))) => {
+            for i in 0..old.len().max(new.len()) {
+                diff_values(&format!("{}[{}]", path, i), old.get(i), new.get(i), out);
+            }
+        }
+        (old, new) if old != new => out.push(PropertyChange {
+            path: path.to_owned(),
+            old: old.cloned(),
+            new: new.cloned(),
+        }),
+        _ => (),
+    }
+}
+
+//----------------------------------------
@@ -0,0 +1,118 @@
+//----------------------------------------
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+//----------------------------------------
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+//----------------------------------------
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing failed
+    Io(io::Error),
+    /// Path is not a Folder or a supported archive
+    NotAPackage(String),
+    /// Asset YAML could not be parsed
+    MalformedYaml(String),
+    /// GUID not found in package
+    MissingGuid(String),
+    /// asset.meta could not be parsed
+    BadMeta(String),
+    /// External converter failed
+    ExternalTool(String),
+    /// Output file exists and overwrite was not requested
+    OutputExists(PathBuf),
+    /// A package check found problems, the report has the details
+    CheckFailed(String),
+    /// FBX could not be parsed
+    MalformedFbx(String),
+    /// Texture could not be decoded or encoded
+    BadTexture(String),
+    /// Packages being merged conflict and the policy is to fail
+    MergeConflict(String),
+    /// Absolute or `..` path in a package
+    UnsafePath(String),
+    /// Link, device, or other non-regular entry in a hardened package
+    UnsupportedEntry(String),
+    /// Entry over the size limit of a hardened package
+    EntryTooLarge(String),
+    /// Entries add up to more than the total size limit of a hardened package
+    PackageTooLarge(String),
+}
+
+//----------------------------------------
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::NotAPackage(msg) => write!(f, "Not a package: {}", msg),
+            Error::MalformedYaml(msg) => write!(f, "Malformed YAML: {}", msg),
+            Error::MissingGuid(guid) => write!(f, "Could not find {} in package", guid),
+            Error::BadMeta(msg) => write!(f, "Bad meta: {}", msg),
+            Error::ExternalTool(msg) => write!(f, "External tool failed: {}", msg),
+            Error::OutputExists(path) => write!(
+                f,
+                "{} already exists, use --force to overwrite",
+                path.display()
+            ),
+            Error::CheckFailed(msg) => write!(f, "Check failed: {}", msg),
+            Error::MalformedFbx(msg) => write!(f, "Malformed FBX: {}", msg),
+            Error::BadTexture(msg) => write!(f, "Bad texture: {}", msg),
+            Error::MergeConflict(msg) => write!(f, "Merge conflict: {}", msg),
+            Error::UnsafePath(path) => write!(f, "Unsafe path: {}", path),
+            Error::UnsupportedEntry(msg) => write!(f, "Unsupported entry: {}", msg),
+            Error::EntryTooLarge(msg) => write!(f, "Entry too large: {}", msg),
+            Error::PackageTooLarge(msg) => write!(f, "Package too large: {}", msg),
+        }
+    }
+}
+
+//----------------------------------------
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+//----------------------------------------
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<walkdir::Error> for Error {
+    fn from(err: walkdir::Error) -> Self {
+        Error::Io(err.into())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Io(err.into())
+    }
+}
+
+impl From<zip::result::ZipError> for Error {
+    fn from(err: zip::result::ZipError) -> Self {
+        Error::Io(err.into())
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(err: serde_yaml::Error) -> Self {
+        Error::MalformedYaml(err.to_string())
+    }
+}
+
+//----------------------------------------
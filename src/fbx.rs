@@ -0,0 +1,724 @@
+//----------------------------------------
+
+// https://code.blender.org/2013/08/fbx-binary-file-format-specification/
+
+//----------------------------------------
+
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+use crate::error::{Error, Result};
+
+//----------------------------------------
+
+const BINARY_MAGIC: &[u8] = b"Kaydara FBX Binary  \x00\x1a\x00";
+const ASCII_MAGIC: &[u8] = b"; FBX";
+
+/// Deepest node nesting read, real files stay well under it
+const MAX_DEPTH: usize = 256;
+
+//----------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Property {
+    Bool(bool),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    BoolArray(Vec<bool>),
+    I32Array(Vec<i32>),
+    I64Array(Vec<i64>),
+    F32Array(Vec<f32>),
+    F64Array(Vec<f64>),
+}
+
+//----------------------------------------
+
+/// A node of the FBX tree, binary and ASCII files read into the same shape
+#[derive(Debug, Clone, Default)]
+pub struct Node {
+    pub name: String,
+    pub properties: Vec<Property>,
+    pub children: Vec<Node>,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Clone, Default)]
+pub struct FbxDocument {
+    /// 7400 for FBX 7.4, etc. 0 when an ASCII file doesn't say.
+    pub version: u32,
+    pub nodes: Vec<Node>,
+}
+
+//----------------------------------------
+
+/// Binary, or ASCII starting with the "; FBX x.y.z project file" comment
+pub fn is_fbx(data: &[u8]) -> bool {
+    data.starts_with(BINARY_MAGIC) || data.starts_with(ASCII_MAGIC)
+}
+
+//----------------------------------------
+
+pub fn parse(data: &[u8]) -> Result<FbxDocument> {
+    if data.starts_with(BINARY_MAGIC) {
+        BinaryReader { data, position: 0 }.document()
+    } else {
+        AsciiReader::new(&String::from_utf8_lossy(data)).document()
+    }
+}
+
+//----------------------------------------
+
+impl Property {
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Property::Bool(b) => Some(b as i64),
+            Property::I16(n) => Some(n as i64),
+            Property::I32(n) => Some(n as i64),
+            Property::I64(n) => Some(n),
+            Property::F32(n) => Some(n as i64),
+            Property::F64(n) => Some(n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Property::F32(n) => Some(n as f64),
+            Property::F64(n) => Some(n),
+            _ => self.as_i64().map(|n| n as f64),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Property::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Property::Bytes(bytes) => Some(bytes),
+            Property::String(s) => Some(s.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn to_f64_vec(&self) -> Option<Vec<f64>> {
+        match self {
+            Property::F64Array(values) => Some(values.clone()),
+            Property::F32Array(values) => Some(values.iter().map(|&n| n as f64).collect()),
+            Property::I32Array(values) => Some(values.iter().map(|&n| n as f64).collect()),
+            Property::I64Array(values) => Some(values.iter().map(|&n| n as f64).collect()),
+            _ => None,
+        }
+    }
+
+    pub fn to_i64_vec(&self) -> Option<Vec<i64>> {
+        match self {
+            Property::I64Array(values) => Some(values.clone()),
+            Property::I32Array(values) => Some(values.iter().map(|&n| n as i64).collect()),
+            Property::F64Array(values) => Some(values.iter().map(|&n| n as i64).collect()),
+            Property::F32Array(values) => Some(values.iter().map(|&n| n as i64).collect()),
+            _ => None,
+        }
+    }
+
+    /// Object ids are numbers from FBX 7, "Model::Name" strings before
+    pub fn to_key(&self) -> Option<String> {
+        match self {
+            Property::String(s) => Some(s.clone()),
+            _ => self.as_i64().map(|id| id.to_string()),
+        }
+    }
+}
+
+//----------------------------------------
+
+impl Node {
+    pub fn child(&self, name: &str) -> Option<&Node> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Node> + 'a {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    pub fn property(&self, index: usize) -> Option<&Property> {
+        self.properties.get(index)
+    }
+
+    /// First property of the named child, e.g. the array of "Vertices"
+    pub fn child_property(&self, name: &str) -> Option<&Property> {
+        self.child(name)?.property(0)
+    }
+
+    /// Values of a `Properties70` "P" (or `Properties60` "Property") entry,
+    /// the properties after its name, type, label, and flags
+    pub fn template_property(&self, name: &str) -> Option<&[Property]> {
+        let (block, entry, skip) = match self.child("Properties70") {
+            Some(block) => (block, "P", 4),
+            None => (self.child("Properties60")?, "Property", 3),
+        };
+
+        block
+            .children_named(entry)
+            .find(|p| p.property(0).and_then(Property::as_str) == Some(name))
+            .map(|p| p.properties.get(skip..).unwrap_or(&[]))
+    }
+
+    pub fn template_f64(&self, name: &str) -> Option<f64> {
+        self.template_property(name)?.first()?.as_f64()
+    }
+
+    pub fn template_vec3(&self, name: &str) -> Option<[f64; 3]> {
+        let values = self.template_property(name)?;
+        Some([
+            values.first()?.as_f64()?,
+            values.get(1)?.as_f64()?,
+            values.get(2)?.as_f64()?,
+        ])
+    }
+}
+
+//----------------------------------------
+
+impl FbxDocument {
+    pub fn node(&self, name: &str) -> Option<&Node> {
+        self.nodes.iter().find(|node| node.name == name)
+    }
+}
+
+//----------------------------------------
+
+struct BinaryReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+//----------------------------------------
+
+impl<'a> BinaryReader<'a> {
+    fn document(mut self) -> Result<FbxDocument> {
+        self.position = BINARY_MAGIC.len();
+        let version = self.u32()?;
+
+        let mut nodes = Vec::new();
+        while self.position < self.data.len() {
+            match self.node(version, 0)? {
+                Some(node) => nodes.push(node),
+                None => break,
+            }
+        }
+
+        Ok(FbxDocument { version, nodes })
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::MalformedFbx(format!("{} at byte {}", message, self.position))
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| self.error("unexpected end of file"))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    /// Offsets and counts grew to 64 bits in FBX 7.5
+    fn size(&mut self, version: u32) -> Result<usize> {
+        let size = if version >= 7500 {
+            self.u64()?
+        } else {
+            self.u32()? as u64
+        };
+        usize::try_from(size).map_err(|_| self.error("size out of range"))
+    }
+
+    fn node(&mut self, version: u32, depth: usize) -> Result<Option<Node>> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nodes nested too deep"));
+        }
+
+        let end_offset = self.size(version)?;
+        let property_count = self.size(version)?;
+        let _property_list_len = self.size(version)?;
+        let name_len = self.u8()? as usize;
+        let name = String::from_utf8_lossy(self.bytes(name_len)?).into_owned();
+
+        // A zeroed record ends a list of nodes
+        if end_offset == 0 {
+            return Ok(None);
+        }
+        if end_offset > self.data.len() || end_offset < self.position {
+            return Err(self.error("bad node end offset"));
+        }
+
+        let mut properties = Vec::with_capacity(property_count.min(1024));
+        for _ in 0..property_count {
+            properties.push(self.property()?);
+        }
+
+        let mut children = Vec::new();
+        while self.position < end_offset {
+            match self.node(version, depth + 1)? {
+                Some(child) => children.push(child),
+                None => break,
+            }
+        }
+        self.position = end_offset;
+
+        Ok(Some(Node {
+            name,
+            properties,
+            children,
+        }))
+    }
+
+    fn property(&mut self) -> Result<Property> {
+        let code = self.u8()?;
+        Ok(match code {
+            b'Y' => Property::I16(i16::from_le_bytes(self.array()?)),
+            b'C' => Property::Bool(self.u8()? != 0),
+            b'I' => Property::I32(i32::from_le_bytes(self.array()?)),
+            b'F' => Property::F32(f32::from_le_bytes(self.array()?)),
+            b'D' => Property::F64(f64::from_le_bytes(self.array()?)),
+            b'L' => Property::I64(i64::from_le_bytes(self.array()?)),
+            b'S' => {
+                let len = self.u32()? as usize;
+                Property::String(binary_string(self.bytes(len)?))
+            }
+            b'R' => {
+                let len = self.u32()? as usize;
+                Property::Bytes(self.bytes(len)?.to_vec())
+            }
+            b'f' => Property::F32Array(
+                self.array_property(4, |b| f32::from_le_bytes(b.try_into().unwrap()))?,
+            ),
+            b'd' => Property::F64Array(
+                self.array_property(8, |b| f64::from_le_bytes(b.try_into().unwrap()))?,
+            ),
+            b'i' => Property::I32Array(
+                self.array_property(4, |b| i32::from_le_bytes(b.try_into().unwrap()))?,
+            ),
+            b'l' => Property::I64Array(
+                self.array_property(8, |b| i64::from_le_bytes(b.try_into().unwrap()))?,
+            ),
+            b'b' => Property::BoolArray(self.array_property(1, |b| b[0] != 0)?),
+            _ => return Err(self.error(&format!("unknown property type {:?}", code as char))),
+        })
+    }
+
+    fn array_property<T>(&mut self, width: usize, convert: fn(&[u8]) -> T) -> Result<Vec<T>> {
+        let len = self.u32()? as usize;
+        let encoding = self.u32()?;
+        let compressed_len = self.u32()? as usize;
+        let raw = self.bytes(compressed_len)?;
+
+        let expected = len
+            .checked_mul(width)
+            .ok_or_else(|| self.error("array too large"))?;
+
+        let data = match encoding {
+            0 => raw.to_vec(),
+            1 => {
+                let mut data = Vec::with_capacity(expected.min(raw.len() * 16));
+                ZlibDecoder::new(raw)
+                    .take(expected as u64)
+                    .read_to_end(&mut data)?;
+                data
+            }
+            _ => return Err(self.error(&format!("unknown array encoding {}", encoding))),
+        };
+
+        if data.len() < expected {
+            return Err(self.error("array shorter than its length"));
+        }
+
+        Ok(data[..expected].chunks_exact(width).map(convert).collect())
+    }
+}
+
+//----------------------------------------
+
+/// Binary names are "Name\x00\x01Class", ASCII writes them "Class::Name"
+fn binary_string(bytes: &[u8]) -> String {
+    let s = String::from_utf8_lossy(bytes);
+    match s.split_once("\x00\x01") {
+        Some((name, class)) => format!("{}::{}", class, name),
+        None => s.into_owned(),
+    }
+}
+
+//----------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// "Name:" starting a node
+    Key(String),
+    Word(String),
+    Str(String),
+    Number(String),
+    /// "*N" before an array block
+    Star,
+    Open,
+    Close,
+    Comma,
+}
+
+//----------------------------------------
+
+struct AsciiReader {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+//----------------------------------------
+
+impl AsciiReader {
+    fn new(text: &str) -> AsciiReader {
+        AsciiReader {
+            tokens: tokenize(text),
+            position: 0,
+        }
+    }
+
+    fn document(mut self) -> Result<FbxDocument> {
+        let mut nodes = Vec::new();
+        while self.position < self.tokens.len() {
+            nodes.push(self.node(0)?);
+        }
+
+        let version = nodes
+            .iter()
+            .find(|node| node.name == "FBXHeaderExtension")
+            .and_then(|header| header.child_property("FBXVersion"))
+            .and_then(Property::as_i64)
+            .unwrap_or(0) as u32;
+
+        Ok(FbxDocument { version, nodes })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .map(|(token, _)| token.clone());
+        self.position += 1;
+        token
+    }
+
+    fn error(&self, message: &str) -> Error {
+        let line = self
+            .tokens
+            .get(self.position.min(self.tokens.len().saturating_sub(1)))
+            .map(|(_, line)| *line)
+            .unwrap_or(0);
+        Error::MalformedFbx(format!("line {}: {}", line, message))
+    }
+
+    fn node(&mut self, depth: usize) -> Result<Node> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nodes nested too deep"));
+        }
+
+        let name = match self.next() {
+            Some(Token::Key(name)) => name,
+            _ => return Err(self.error("expected a node name")),
+        };
+
+        let mut properties = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::Str(_)) | Some(Token::Number(_)) | Some(Token::Word(_)) => {
+                    properties.push(self.value()?);
+                }
+                Some(Token::Star) => {
+                    self.next();
+                    properties.push(self.array()?);
+                }
+                Some(Token::Comma) => {
+                    self.next();
+                }
+                _ => break,
+            }
+        }
+
+        let mut children = Vec::new();
+        if self.peek() == Some(&Token::Open) {
+            self.next();
+            loop {
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.next();
+                        break;
+                    }
+                    Some(Token::Key(_)) => children.push(self.node(depth + 1)?),
+                    None => return Err(self.error("unclosed {")),
+                    _ => return Err(self.error("expected a node name or }")),
+                }
+            }
+        }
+
+        Ok(Node {
+            name,
+            properties,
+            children,
+        })
+    }
+
+    fn value(&mut self) -> Result<Property> {
+        Ok(match self.next() {
+            Some(Token::Str(s)) => Property::String(s),
+            Some(Token::Word(s)) => Property::String(s),
+            Some(Token::Number(n)) => number(&n),
+            _ => return Err(self.error("expected a value")),
+        })
+    }
+
+    /// "*N { a: 1,2,3 }", the count is skipped, the values say it all
+    fn array(&mut self) -> Result<Property> {
+        if let Some(Token::Number(_)) = self.peek() {
+            self.next();
+        }
+        if self.next() != Some(Token::Open) {
+            return Err(self.error("expected { after *N"));
+        }
+
+        let mut values = Vec::new();
+        loop {
+            match self.next() {
+                Some(Token::Close) => break,
+                Some(Token::Number(n)) => values.push(number(&n)),
+                Some(Token::Key(_)) | Some(Token::Comma) => (),
+                _ => return Err(self.error("bad array")),
+            }
+        }
+
+        let all_ints = values.iter().all(|v| matches!(v, Property::I64(_)));
+        Ok(if all_ints {
+            Property::I64Array(values.iter().filter_map(Property::as_i64).collect())
+        } else {
+            Property::F64Array(values.iter().filter_map(Property::as_f64).collect())
+        })
+    }
+}
+
+//----------------------------------------
+
+fn number(s: &str) -> Property {
+    match s.parse::<i64>() {
+        Ok(n) => Property::I64(n),
+        Err(_) => Property::F64(s.parse().unwrap_or(0.0)),
+    }
+}
+
+//----------------------------------------
+
+fn tokenize(text: &str) -> Vec<(Token, usize)> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            ';' => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '{' | '}' | ',' | '*' => {
+                chars.next();
+                tokens.push((
+                    match c {
+                        '{' => Token::Open,
+                        '}' => Token::Close,
+                        ',' => Token::Comma,
+                        _ => Token::Star,
+                    },
+                    line,
+                ));
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    s.push(c);
+                }
+                // ASCII FBX escapes quotes as &quot;
+                tokens.push((Token::Str(s.replace("&quot;", "\"")), line));
+            }
+            _ => {
+                let mut s = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "{},;\":".contains(c) {
+                        break;
+                    }
+                    s.push(c);
+                    chars.next();
+                }
+
+                if chars.peek() == Some(&':') {
+                    chars.next();
+                    tokens.push((Token::Key(s), line));
+                } else if s.is_empty() {
+                    // Stray character, skip it
+                    chars.next();
+                } else if s.starts_with(|c: char| c.is_ascii_digit() || "-+.".contains(c)) {
+                    tokens.push((Token::Number(s), line));
+                } else {
+                    tokens.push((Token::Word(s), line));
+                }
+            }
+        }
+    }
+
+    tokens
+}
+
+//----------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The same quad, binary with zlib compressed vertices, and ASCII
+    const BINARY: &[u8] = include_bytes!("../tests/fixtures/quad_binary.fbx");
+    const ASCII: &[u8] = include_bytes!("../tests/fixtures/quad_ascii.fbx");
+
+    #[test]
+    fn binary_and_ascii_read_into_the_same_tree() {
+        for data in [BINARY, ASCII] {
+            assert!(is_fbx(data));
+            let document = parse(data).unwrap();
+            assert_eq!(document.version, 7400);
+
+            let objects = document.node("Objects").unwrap();
+            let names = objects
+                .children
+                .iter()
+                .map(|node| node.name.as_str())
+                .collect::<Vec<&str>>();
+            assert_eq!(names, ["Geometry", "Model", "Material"]);
+
+            let geometry = objects.child("Geometry").unwrap();
+            assert_eq!(geometry.property(0).and_then(Property::as_i64), Some(100));
+            assert_eq!(
+                geometry.child_property("Vertices").unwrap().to_f64_vec(),
+                Some(vec![
+                    -1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 1.0, 1.0, 0.0, -1.0, 1.0, 0.0
+                ])
+            );
+            assert_eq!(
+                geometry
+                    .child_property("PolygonVertexIndex")
+                    .unwrap()
+                    .to_i64_vec(),
+                Some(vec![0, 1, 2, -4])
+            );
+            assert_eq!(
+                geometry
+                    .child("LayerElementUV")
+                    .and_then(|layer| layer.child_property("MappingInformationType"))
+                    .and_then(Property::as_str),
+                Some("ByPolygonVertex")
+            );
+
+            let model = objects.child("Model").unwrap();
+            assert_eq!(
+                model.property(1).and_then(Property::as_str),
+                Some("Model::Quad")
+            );
+            assert_eq!(
+                model.template_vec3("Lcl Translation"),
+                Some([1.0, 2.0, 3.0])
+            );
+
+            let connections = document.node("Connections").unwrap();
+            assert_eq!(connections.children_named("C").count(), 3);
+        }
+    }
+
+    #[test]
+    fn truncated_and_unbalanced_files_are_malformed() {
+        let truncated = parse(&BINARY[..BINARY.len() / 2]);
+        assert!(matches!(truncated, Err(Error::MalformedFbx(_))));
+
+        let unclosed = parse(b"; FBX 7.4.0 project file\nObjects:  {\n\tModel: 1 {\n");
+        assert!(matches!(unclosed, Err(Error::MalformedFbx(_))));
+    }
+
+    #[test]
+    fn deep_nesting_is_malformed() {
+        let ascii = format!(
+            "; FBX 7.4.0 project file\n{}{}",
+            "N: {".repeat(MAX_DEPTH + 2),
+            "}".repeat(MAX_DEPTH + 2)
+        );
+        assert!(matches!(
+            parse(ascii.as_bytes()),
+            Err(Error::MalformedFbx(message)) if message.contains("too deep")
+        ));
+
+        // Each record's end offset covers the records nested in it
+        let count = MAX_DEPTH + 2;
+        let mut binary = BINARY_MAGIC.to_vec();
+        binary.extend(7400u32.to_le_bytes());
+        let start = binary.len();
+        let end = start + count * 14;
+        for _ in 0..count {
+            binary.extend((end as u32).to_le_bytes());
+            binary.extend([0; 8]);
+            binary.extend([1, b'N']);
+        }
+        binary.resize(end, 0);
+        assert!(matches!(
+            parse(&binary),
+            Err(Error::MalformedFbx(message)) if message.contains("too deep")
+        ));
+    }
+}
@@ -0,0 +1,163 @@
+//----------------------------------------
+
+// https://github.com/barcoderdev/FBX2glTF
+
+//----------------------------------------
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+
+//----------------------------------------
+
+#[cfg(windows)]
+const PROGRAM: &str = "FBX2glTF.exe";
+#[cfg(not(windows))]
+const PROGRAM: &str = "FBX2glTF";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+//----------------------------------------
+
+/// How to run the external FBX2glTF converter
+#[derive(Debug, Clone, Default)]
+pub struct Fbx2Gltf {
+    /// Converter binary, found by `find_program` when None
+    pub program: Option<PathBuf>,
+    /// Passed after `-IO`, e.g. `--khr-materials-unlit`
+    pub args: Vec<String>,
+    /// Kill the converter when it runs longer
+    pub timeout: Option<Duration>,
+}
+
+//----------------------------------------
+
+impl Fbx2Gltf {
+    /// Convert FBX bytes to GLB, feeding stdin and draining stdout and
+    /// stderr concurrently so large meshes cannot fill a pipe and stall.
+    pub fn convert(&self, fbx: &[u8]) -> Result<Vec<u8>> {
+        let program = match &self.program {
+            Some(program) => program.clone(),
+            None => find_program()?,
+        };
+        let name = program.display().to_string();
+
+        let mut child = Command::new(&program)
+            .arg("-IO")
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| Error::ExternalTool(format!("{}: {}", name, err)))?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+
+        thread::scope(|scope| {
+            let writer = scope.spawn(move || match stdin.write_all(fbx) {
+                // The converter quit early, its exit status says why
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                result => result,
+            });
+            let output = scope.spawn(move || {
+                let mut buffer = Vec::new();
+                stdout.read_to_end(&mut buffer).map(|_| buffer)
+            });
+            let errors = scope.spawn(move || {
+                let mut buffer = Vec::new();
+                stderr.read_to_end(&mut buffer).map(|_| buffer)
+            });
+
+            let status = wait(&mut child, self.timeout);
+
+            let written = writer.join().unwrap();
+            let output = output.join().unwrap();
+            let errors = errors.join().unwrap();
+
+            let status = match status? {
+                Some(status) => status,
+                None => {
+                    return Err(Error::ExternalTool(format!(
+                        "{} timed out after {:?}",
+                        name,
+                        self.timeout.unwrap_or_default()
+                    )))
+                }
+            };
+
+            if !status.success() {
+                let errors = errors.unwrap_or_default();
+                let message = String::from_utf8_lossy(&errors);
+                return Err(Error::ExternalTool(format!(
+                    "{} {}: {}",
+                    name,
+                    status,
+                    message.trim()
+                )));
+            }
+
+            written?;
+            Ok(output?)
+        })
+    }
+}
+
+//----------------------------------------
+
+/// None when the timeout ran out and the child was killed
+fn wait(child: &mut Child, timeout: Option<Duration>) -> Result<Option<std::process::ExitStatus>> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Ok(Some(child.wait()?)),
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            // Already exited if kill fails, reap it either way
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+//----------------------------------------
+
+/// FBX2glTF next to the executable, in the working directory, or in PATH
+pub fn find_program() -> Result<PathBuf> {
+    let beside_exe = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join(PROGRAM)));
+
+    let in_path = std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).collect::<Vec<PathBuf>>())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|dir| dir.join(PROGRAM));
+
+    beside_exe
+        .into_iter()
+        .chain(std::iter::once(Path::new(".").join(PROGRAM)))
+        .chain(in_path)
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            Error::ExternalTool(format!(
+                "{} not found next to the executable, in the working directory, or in PATH, \
+                 use --fbx2gltf-path or FBX2GLTF",
+                PROGRAM
+            ))
+        })
+}
+
+//----------------------------------------
@@ -0,0 +1,1202 @@
+//----------------------------------------
+
+// https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html
+
+//----------------------------------------
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde_json::{json, Map, Value};
+
+use crate::error::{Error, Result};
+use crate::fbx::{self, FbxDocument, Node, Property};
+
+//----------------------------------------
+
+/// FBX KTime units per second
+const TICKS_PER_SECOND: f64 = 46_186_158_000.0;
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+//----------------------------------------
+
+/// Convert a binary or ASCII FBX to GLB: meshes with normals, UVs and
+/// per-polygon materials, diffuse textures, skins, and T/R/S animations.
+/// Pivots and rotation offsets are ignored, pre-rotation is kept.
+pub fn fbx_to_glb(data: &[u8]) -> Result<Vec<u8>> {
+    let document = fbx::parse(data)?;
+    let scene = Scene::new(&document)?;
+    let mut gltf = Gltf::default();
+
+    let root = scene.convert(&mut gltf)?;
+    Ok(gltf.glb(root))
+}
+
+//----------------------------------------
+
+/// Objects and their connections, keyed by object id
+struct Scene<'a> {
+    document: &'a FbxDocument,
+    objects: HashMap<String, &'a Node>,
+    /// Object keys in file order
+    order: Vec<String>,
+    /// child -> (parent, property) and parent -> (child, property)
+    parents: HashMap<String, Vec<(String, Option<String>)>>,
+    children: HashMap<String, Vec<(String, Option<String>)>>,
+}
+
+//----------------------------------------
+
+/// Per control point (joint, weight) pairs and the glTF skin they belong to
+struct SkinData {
+    skin: usize,
+    weights: Vec<Vec<(u16, f32)>>,
+}
+
+//----------------------------------------
+
+impl<'a> Scene<'a> {
+    fn new(document: &'a FbxDocument) -> Result<Scene<'a>> {
+        let objects_node = document
+            .node("Objects")
+            .ok_or_else(|| Error::MalformedFbx("no Objects section".to_owned()))?;
+
+        let mut objects = HashMap::new();
+        let mut order = Vec::new();
+        for object in &objects_node.children {
+            if let Some(key) = object.property(0).and_then(Property::to_key) {
+                order.push(key.clone());
+                objects.insert(key, object);
+            }
+        }
+
+        let mut parents = HashMap::<String, Vec<(String, Option<String>)>>::new();
+        let mut children = HashMap::<String, Vec<(String, Option<String>)>>::new();
+
+        let connections = document
+            .node("Connections")
+            .map(|node| node.children.as_slice())
+            .unwrap_or(&[]);
+        for connection in connections {
+            let (child, parent) = match (
+                connection.property(1).and_then(Property::to_key),
+                connection.property(2).and_then(Property::to_key),
+            ) {
+                (Some(child), Some(parent)) => (child, parent),
+                _ => continue,
+            };
+            let property = connection
+                .property(3)
+                .and_then(Property::as_str)
+                .map(|s| s.to_owned());
+
+            parents
+                .entry(child.clone())
+                .or_default()
+                .push((parent.clone(), property.clone()));
+            children.entry(parent).or_default().push((child, property));
+        }
+
+        Ok(Scene {
+            document,
+            objects,
+            order,
+            parents,
+            children,
+        })
+    }
+
+    fn object(&self, key: &str) -> Option<&'a Node> {
+        self.objects.get(key).copied()
+    }
+
+    /// Connected children of `key` that are `kind` objects ("Model", ...)
+    fn children_of(&self, key: &str, kind: &str) -> Vec<(String, &'a Node)> {
+        self.children
+            .get(key)
+            .into_iter()
+            .flatten()
+            .filter_map(|(child, _)| Some((child.clone(), self.object(child)?)))
+            .filter(|(_, node)| node.name == kind)
+            .collect()
+    }
+
+    fn parents_of(&self, key: &str, kind: &str) -> Vec<(String, &'a Node, Option<String>)> {
+        self.parents
+            .get(key)
+            .into_iter()
+            .flatten()
+            .filter_map(|(parent, property)| {
+                Some((parent.clone(), self.object(parent)?, property.clone()))
+            })
+            .filter(|(_, node, _)| node.name == kind)
+            .collect()
+    }
+
+    fn objects_named(&self, kind: &str) -> Vec<(String, &'a Node)> {
+        self.order
+            .iter()
+            .filter_map(|key| Some((key.clone(), self.object(key)?)))
+            .filter(|(_, node)| node.name == kind)
+            .collect()
+    }
+
+    fn global_f64(&self, name: &str) -> Option<f64> {
+        self.document.node("GlobalSettings")?.template_f64(name)
+    }
+
+    fn convert(&self, gltf: &mut Gltf) -> Result<usize> {
+        let mut model_nodes = HashMap::<String, usize>::new();
+
+        for (key, model) in self.objects_named("Model") {
+            let index = gltf.nodes.len();
+            gltf.nodes.push(model_node(model));
+            model_nodes.insert(key, index);
+        }
+
+        // Hierarchy, models without a model parent hang off the root
+        let mut roots = Vec::new();
+        for (key, _) in self.objects_named("Model") {
+            let index = model_nodes[&key];
+            match self
+                .parents_of(&key, "Model")
+                .first()
+                .and_then(|(parent, _, _)| model_nodes.get(parent))
+            {
+                Some(&parent) => push_child(&mut gltf.nodes[parent], index),
+                None => roots.push(index),
+            }
+        }
+
+        let mut materials = HashMap::<String, usize>::new();
+        for (key, model) in self.objects_named("Model") {
+            let geometry = self
+                .children_of(&key, "Geometry")
+                .into_iter()
+                .next()
+                .or_else(|| model.child("Vertices").map(|_| (key.clone(), model)));
+            let (geometry_key, geometry) = match geometry {
+                Some(geometry) => geometry,
+                None => continue,
+            };
+
+            let slots = self
+                .children_of(&key, "Material")
+                .into_iter()
+                .map(|(key, material)| {
+                    *materials
+                        .entry(key)
+                        .or_insert_with(|| self.material(gltf, material))
+                })
+                .collect::<Vec<usize>>();
+
+            let skin = self.skin(gltf, &geometry_key, geometry, &model_nodes);
+
+            if let Some(mesh) = build_mesh(gltf, geometry, &slots, skin.as_ref())? {
+                let node = &mut gltf.nodes[model_nodes[&key]];
+                node["mesh"] = mesh.into();
+                if let Some(skin) = skin {
+                    node["skin"] = skin.skin.into();
+                }
+            }
+        }
+
+        self.animations(gltf, &model_nodes);
+
+        // FBX defaults to centimeters, glTF is meters and Y up
+        let unit = self.global_f64("UnitScaleFactor").unwrap_or(1.0) / 100.0;
+        let mut root = json!({
+            "name": "RootNode",
+            "scale": [unit, unit, unit],
+            "children": roots,
+        });
+        if self.global_f64("UpAxis") == Some(2.0) {
+            let half = std::f64::consts::FRAC_1_SQRT_2;
+            root["rotation"] = json!([-half, 0.0, 0.0, half]);
+        }
+        if roots.is_empty() {
+            root.as_object_mut().unwrap().remove("children");
+        }
+
+        gltf.nodes.push(root);
+        Ok(gltf.nodes.len() - 1)
+    }
+
+    fn material(&self, gltf: &mut Gltf, material: &Node) -> usize {
+        let diffuse = material
+            .template_vec3("DiffuseColor")
+            .or_else(|| material.template_vec3("Diffuse"))
+            .unwrap_or([0.8, 0.8, 0.8]);
+        let opacity = material
+            .template_f64("Opacity")
+            .unwrap_or_else(|| 1.0 - material.template_f64("TransparencyFactor").unwrap_or(0.0));
+        let emissive_factor = material.template_f64("EmissiveFactor").unwrap_or(1.0);
+        let emissive = material
+            .template_vec3("EmissiveColor")
+            .unwrap_or([0.0; 3])
+            .map(|c| (c * emissive_factor).clamp(0.0, 1.0));
+
+        let mut pbr = json!({
+            "baseColorFactor": [diffuse[0], diffuse[1], diffuse[2], opacity.clamp(0.0, 1.0)],
+            "metallicFactor": 0.0,
+            "roughnessFactor": 1.0,
+        });
+
+        let key = material
+            .property(0)
+            .and_then(Property::to_key)
+            .unwrap_or_default();
+        if let Some(texture) = self.diffuse_texture(gltf, &key) {
+            pbr["baseColorTexture"] = json!({ "index": texture });
+        }
+
+        let mut value = json!({
+            "name": object_name(material),
+            "pbrMetallicRoughness": pbr,
+        });
+        if emissive.iter().any(|&c| c > 0.0) {
+            value["emissiveFactor"] = json!(emissive);
+        }
+        if opacity < 1.0 {
+            value["alphaMode"] = "BLEND".into();
+        }
+
+        gltf.materials.push(value);
+        gltf.materials.len() - 1
+    }
+
+    /// Texture on the material's DiffuseColor, embedded when the FBX
+    /// carries the image, otherwise referenced by its relative path
+    fn diffuse_texture(&self, gltf: &mut Gltf, material: &str) -> Option<usize> {
+        let (texture_key, texture) = self
+            .children
+            .get(material)?
+            .iter()
+            .filter(|(_, property)| property.as_deref() == Some("DiffuseColor"))
+            .find_map(|(key, _)| {
+                let node = self.object(key)?;
+                (node.name == "Texture").then(|| (key.clone(), node))
+            })?;
+
+        let video = self.children_of(&texture_key, "Video").into_iter().next();
+        let content = video
+            .as_ref()
+            .and_then(|(_, video)| video.child_property("Content"))
+            .and_then(Property::as_bytes)
+            .filter(|content| !content.is_empty());
+
+        let image = match content.and_then(|content| Some((image_mime(content)?, content))) {
+            Some((mime, content)) => {
+                let view = gltf.view(content, None);
+                json!({ "bufferView": view, "mimeType": mime })
+            }
+            None => {
+                let path = texture
+                    .child_property("RelativeFilename")
+                    .or_else(|| texture.child_property("FileName"))
+                    .and_then(Property::as_str)?
+                    .replace('\\', "/");
+                json!({ "uri": path })
+            }
+        };
+
+        gltf.images.push(image);
+        gltf.textures
+            .push(json!({ "source": gltf.images.len() - 1 }));
+        Some(gltf.textures.len() - 1)
+    }
+
+    fn skin(
+        &self,
+        gltf: &mut Gltf,
+        geometry_key: &str,
+        geometry: &Node,
+        model_nodes: &HashMap<String, usize>,
+    ) -> Option<SkinData> {
+        let (skin_key, _) = self
+            .children_of(geometry_key, "Deformer")
+            .into_iter()
+            .find(|(_, deformer)| subclass(deformer) == Some("Skin"))?;
+
+        let control_points = geometry
+            .child_property("Vertices")
+            .and_then(Property::to_f64_vec)
+            .map(|vertices| vertices.len() / 3)
+            .unwrap_or(0);
+
+        let mut weights = vec![Vec::new(); control_points];
+        let mut joints = Vec::new();
+        let mut inverse_binds = Vec::new();
+
+        for (cluster_key, cluster) in self.children_of(&skin_key, "Deformer") {
+            let bone = self
+                .children_of(&cluster_key, "Model")
+                .into_iter()
+                .find_map(|(key, _)| model_nodes.get(&key).copied());
+            let bone = match bone {
+                Some(bone) => bone,
+                None => continue,
+            };
+
+            let joint = joints.len() as u16;
+            joints.push(bone);
+
+            let transform = matrix(cluster.child_property("Transform"));
+            let link = matrix(cluster.child_property("TransformLink"));
+            inverse_binds.extend(mat4_mul(&mat4_inverse(&link), &transform).map(|v| v as f32));
+
+            let indexes = cluster
+                .child_property("Indexes")
+                .and_then(Property::to_i64_vec)
+                .unwrap_or_default();
+            let values = cluster
+                .child_property("Weights")
+                .and_then(Property::to_f64_vec)
+                .unwrap_or_default();
+            for (&index, &weight) in indexes.iter().zip(&values) {
+                if let Some(list) = weights.get_mut(index as usize) {
+                    list.push((joint, weight as f32));
+                }
+            }
+        }
+
+        if joints.is_empty() {
+            return None;
+        }
+
+        let inverse_bind_matrices = gltf.accessor(&inverse_binds, "MAT4", None, false);
+        gltf.skins.push(json!({
+            "joints": joints,
+            "inverseBindMatrices": inverse_bind_matrices,
+        }));
+
+        Some(SkinData {
+            skin: gltf.skins.len() - 1,
+            weights,
+        })
+    }
+
+    fn animations(&self, gltf: &mut Gltf, model_nodes: &HashMap<String, usize>) {
+        for (stack_key, stack) in self.objects_named("AnimationStack") {
+            let mut samplers = Vec::new();
+            let mut channels = Vec::new();
+
+            let curve_nodes = self
+                .children_of(&stack_key, "AnimationLayer")
+                .into_iter()
+                .flat_map(|(layer, _)| self.children_of(&layer, "AnimationCurveNode"));
+
+            for (curve_node_key, curve_node) in curve_nodes {
+                let target = self
+                    .parents_of(&curve_node_key, "Model")
+                    .into_iter()
+                    .find_map(|(model_key, model, property)| {
+                        let node = *model_nodes.get(&model_key)?;
+                        Some((node, model, property?))
+                    });
+                let (node, model, property) = match target {
+                    Some(target) => target,
+                    None => continue,
+                };
+
+                let path = match property.as_str() {
+                    "Lcl Translation" => "translation",
+                    "Lcl Rotation" => "rotation",
+                    "Lcl Scaling" => "scale",
+                    _ => continue,
+                };
+
+                let curves = ["d|X", "d|Y", "d|Z"].map(|component| {
+                    let curve = self
+                        .children
+                        .get(&curve_node_key)
+                        .into_iter()
+                        .flatten()
+                        .find(|(_, property)| property.as_deref() == Some(component))
+                        .and_then(|(key, _)| self.object(key))
+                        .and_then(Curve::new);
+                    let default = curve_node.template_f64(component).unwrap_or(0.0);
+                    (curve, default)
+                });
+
+                let times = curves
+                    .iter()
+                    .filter_map(|(curve, _)| curve.as_ref())
+                    .flat_map(|curve| curve.times.iter().copied())
+                    .collect::<BTreeSet<i64>>();
+                if times.is_empty() {
+                    continue;
+                }
+
+                let mut output = Vec::new();
+                for &time in &times {
+                    let value = curves.each_ref().map(|(curve, default)| {
+                        curve.as_ref().map_or(*default, |curve| curve.sample(time))
+                    });
+
+                    if path == "rotation" {
+                        let rotation = model_rotation(model, value);
+                        output.extend(rotation.map(|v| v as f32));
+                    } else {
+                        output.extend(value.map(|v| v as f32));
+                    }
+                }
+
+                let input = times
+                    .iter()
+                    .map(|&time| (time as f64 / TICKS_PER_SECOND) as f32)
+                    .collect::<Vec<f32>>();
+                let input = gltf.accessor(&input, "SCALAR", None, true);
+                let kind = if path == "rotation" { "VEC4" } else { "VEC3" };
+                let output = gltf.accessor(&output, kind, None, false);
+
+                samplers
+                    .push(json!({ "input": input, "output": output, "interpolation": "LINEAR" }));
+                channels.push(json!({
+                    "sampler": samplers.len() - 1,
+                    "target": { "node": node, "path": path },
+                }));
+            }
+
+            if !channels.is_empty() {
+                gltf.animations.push(json!({
+                    "name": object_name(stack),
+                    "samplers": samplers,
+                    "channels": channels,
+                }));
+            }
+        }
+    }
+}
+
+//----------------------------------------
+
+struct Curve {
+    times: Vec<i64>,
+    values: Vec<f64>,
+}
+
+//----------------------------------------
+
+impl Curve {
+    fn new(node: &Node) -> Option<Curve> {
+        let times = node.child_property("KeyTime")?.to_i64_vec()?;
+        let values = node.child_property("KeyValueFloat")?.to_f64_vec()?;
+        (!times.is_empty() && times.len() == values.len()).then_some(Curve { times, values })
+    }
+
+    /// Linear between keys, held before the first and after the last
+    fn sample(&self, time: i64) -> f64 {
+        match self.times.binary_search(&time) {
+            Ok(i) => self.values[i],
+            Err(0) => self.values[0],
+            Err(i) if i >= self.times.len() => self.values[self.times.len() - 1],
+            Err(i) => {
+                let (t0, t1) = (self.times[i - 1] as f64, self.times[i] as f64);
+                let t = (time as f64 - t0) / (t1 - t0);
+                self.values[i - 1] + (self.values[i] - self.values[i - 1]) * t
+            }
+        }
+    }
+}
+
+//----------------------------------------
+
+fn model_node(model: &Node) -> Value {
+    let mut node = Map::new();
+    node.insert("name".into(), object_name(model).into());
+
+    let translation = model.template_vec3("Lcl Translation").unwrap_or([0.0; 3]);
+    let rotation = model_rotation(
+        model,
+        model.template_vec3("Lcl Rotation").unwrap_or([0.0; 3]),
+    );
+    let scale = model.template_vec3("Lcl Scaling").unwrap_or([1.0; 3]);
+
+    if translation != [0.0; 3] {
+        node.insert("translation".into(), json!(translation));
+    }
+    if rotation != [0.0, 0.0, 0.0, 1.0] {
+        node.insert("rotation".into(), json!(rotation));
+    }
+    if scale != [1.0; 3] {
+        node.insert("scale".into(), json!(scale));
+    }
+
+    Value::Object(node)
+}
+
+//----------------------------------------
+
+/// PreRotation * Lcl Rotation, both Euler degrees in the model's order
+fn model_rotation(model: &Node, rotation: [f64; 3]) -> [f64; 4] {
+    let order = model.template_f64("RotationOrder").unwrap_or(0.0) as usize;
+    let pre = model.template_vec3("PreRotation").unwrap_or([0.0; 3]);
+    quat_mul(euler_quat(pre, 0), euler_quat(rotation, order))
+}
+
+//----------------------------------------
+
+fn push_child(node: &mut Value, child: usize) {
+    match node.get_mut("children").and_then(Value::as_array_mut) {
+        Some(children) => children.push(child.into()),
+        None => node["children"] = json!([child]),
+    }
+}
+
+//----------------------------------------
+
+/// "Model::Cube" -> "Cube"
+fn object_name(node: &Node) -> String {
+    node.properties
+        .iter()
+        .find_map(Property::as_str)
+        .map(|name| name.split_once("::").map_or(name, |(_, name)| name))
+        .unwrap_or("")
+        .to_owned()
+}
+
+//----------------------------------------
+
+/// "Mesh", "LimbNode", "Skin", "Cluster", etc, the last string property
+fn subclass(node: &Node) -> Option<&str> {
+    node.properties.iter().rev().find_map(Property::as_str)
+}
+
+//----------------------------------------
+
+fn image_mime(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG") {
+        Some("image/png")
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        Some("image/jpeg")
+    } else {
+        None
+    }
+}
+
+//----------------------------------------
+
+/// A LayerElementNormal, LayerElementUV, etc
+struct Layer {
+    values: Vec<f64>,
+    indices: Option<Vec<i64>>,
+    mapping: String,
+    size: usize,
+}
+
+//----------------------------------------
+
+impl Layer {
+    fn new(geometry: &Node, element: &str, data: &str, index: &str, size: usize) -> Option<Layer> {
+        let layer = geometry.child(element)?;
+        let values = layer.child_property(data)?.to_f64_vec()?;
+        let text = |name| {
+            layer
+                .child_property(name)
+                .and_then(Property::as_str)
+                .unwrap_or("")
+                .to_owned()
+        };
+
+        let indices = match text("ReferenceInformationType").as_str() {
+            "Direct" | "" => None,
+            _ => layer.child_property(index).and_then(Property::to_i64_vec),
+        };
+
+        Some(Layer {
+            values,
+            indices,
+            mapping: text("MappingInformationType"),
+            size,
+        })
+    }
+
+    fn get(&self, polygon_vertex: usize, control_point: usize, polygon: usize) -> Option<&[f64]> {
+        let i = match self.mapping.as_str() {
+            "ByVertex" | "ByVertice" | "ByControlPoint" => control_point,
+            "ByPolygon" => polygon,
+            "AllSame" => 0,
+            _ => polygon_vertex,
+        };
+        let i = match &self.indices {
+            Some(indices) => usize::try_from(*indices.get(i)?).ok()?,
+            None => i,
+        };
+        let start = i.checked_mul(self.size)?;
+        self.values.get(start..start.checked_add(self.size)?)
+    }
+}
+
+//----------------------------------------
+
+/// None for geometry without vertices or polygons
+fn build_mesh(
+    gltf: &mut Gltf,
+    geometry: &Node,
+    materials: &[usize],
+    skin: Option<&SkinData>,
+) -> Result<Option<usize>> {
+    let vertices = geometry
+        .child_property("Vertices")
+        .and_then(Property::to_f64_vec);
+    let polygon_indices = geometry
+        .child_property("PolygonVertexIndex")
+        .and_then(Property::to_i64_vec);
+    let (vertices, polygon_indices) = match (vertices, polygon_indices) {
+        (Some(vertices), Some(polygon_indices)) => (vertices, polygon_indices),
+        _ => return Ok(None),
+    };
+
+    let normals = Layer::new(geometry, "LayerElementNormal", "Normals", "NormalsIndex", 3);
+    let uvs = Layer::new(geometry, "LayerElementUV", "UV", "UVIndex", 2);
+    let slots = geometry.child("LayerElementMaterial").and_then(|layer| {
+        let values = layer.child_property("Materials")?.to_i64_vec()?;
+        let all_same = layer
+            .child_property("MappingInformationType")
+            .and_then(Property::as_str)
+            == Some("AllSame");
+        Some((values, all_same))
+    });
+
+    let mut positions = Vec::<f32>::new();
+    let mut normal_data = Vec::<f32>::new();
+    let mut uv_data = Vec::<f32>::new();
+    let mut joint_data = Vec::<u16>::new();
+    let mut weight_data = Vec::<f32>::new();
+
+    // Unique (control point, normal, uv) -> vertex
+    let mut unique = HashMap::<(usize, [u64; 3], [u64; 2]), u32>::new();
+    // Material slot -> triangle indices
+    let mut primitives = std::collections::BTreeMap::<usize, Vec<u32>>::new();
+
+    let mut polygon = Vec::new();
+    let mut polygon_index = 0usize;
+
+    for (polygon_vertex, &index) in polygon_indices.iter().enumerate() {
+        // The last index of each polygon is stored as -(index + 1)
+        let control_point = if index < 0 { !index } else { index } as usize;
+        let position = control_point
+            .checked_mul(3)
+            .and_then(|start| vertices.get(start..start.checked_add(3)?))
+            .ok_or_else(|| {
+                Error::MalformedFbx(format!(
+                    "{}: polygon vertex {} uses control point {} of {}",
+                    object_name(geometry),
+                    polygon_vertex,
+                    control_point,
+                    vertices.len() / 3
+                ))
+            })?;
+
+        let normal = normals
+            .as_ref()
+            .and_then(|layer| layer.get(polygon_vertex, control_point, polygon_index))
+            .map(|n| [n[0], n[1], n[2]]);
+        let uv = uvs
+            .as_ref()
+            .and_then(|layer| layer.get(polygon_vertex, control_point, polygon_index))
+            .map(|uv| [uv[0], 1.0 - uv[1]]);
+
+        let key = (
+            control_point,
+            normal.unwrap_or([0.0; 3]).map(f64::to_bits),
+            uv.unwrap_or([0.0; 2]).map(f64::to_bits),
+        );
+        let vertex = *unique.entry(key).or_insert_with(|| {
+            positions.extend(position.iter().map(|&v| v as f32));
+            if normals.is_some() {
+                let n = normal.unwrap_or([0.0, 1.0, 0.0]);
+                let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+                let n = if len > 0.0 {
+                    n.map(|c| c / len)
+                } else {
+                    [0.0, 1.0, 0.0]
+                };
+                normal_data.extend(n.map(|c| c as f32));
+            }
+            if uvs.is_some() {
+                uv_data.extend(uv.unwrap_or([0.0; 2]).map(|c| c as f32));
+            }
+            if let Some(skin) = skin {
+                let (joints, weights) = vertex_weights(skin.weights.get(control_point));
+                joint_data.extend(joints);
+                weight_data.extend(weights);
+            }
+            (positions.len() / 3 - 1) as u32
+        });
+        polygon.push(vertex);
+
+        if index < 0 {
+            let slot = match &slots {
+                Some((values, true)) => values.first().copied().unwrap_or(0),
+                Some((values, false)) => values.get(polygon_index).copied().unwrap_or(0),
+                None => 0,
+            };
+            let triangles = primitives.entry(slot.max(0) as usize).or_default();
+            for i in 1..polygon.len().saturating_sub(1) {
+                triangles.extend([polygon[0], polygon[i], polygon[i + 1]]);
+            }
+            polygon.clear();
+            polygon_index += 1;
+        }
+    }
+
+    if primitives.values().all(Vec::is_empty) {
+        return Ok(None);
+    }
+
+    let mut attributes = Map::new();
+    attributes.insert(
+        "POSITION".into(),
+        gltf.accessor(&positions, "VEC3", Some(ARRAY_BUFFER), true)
+            .into(),
+    );
+    if !normal_data.is_empty() {
+        attributes.insert(
+            "NORMAL".into(),
+            gltf.accessor(&normal_data, "VEC3", Some(ARRAY_BUFFER), false)
+                .into(),
+        );
+    }
+    if !uv_data.is_empty() {
+        attributes.insert(
+            "TEXCOORD_0".into(),
+            gltf.accessor(&uv_data, "VEC2", Some(ARRAY_BUFFER), false)
+                .into(),
+        );
+    }
+    if !joint_data.is_empty() {
+        attributes.insert("JOINTS_0".into(), gltf.joints_accessor(&joint_data).into());
+        attributes.insert(
+            "WEIGHTS_0".into(),
+            gltf.accessor(&weight_data, "VEC4", Some(ARRAY_BUFFER), false)
+                .into(),
+        );
+    }
+
+    let primitives = primitives
+        .into_iter()
+        .filter(|(_, indices)| !indices.is_empty())
+        .map(|(slot, indices)| {
+            let mut primitive = json!({
+                "attributes": attributes,
+                "indices": gltf.indices_accessor(&indices),
+            });
+            if let Some(&material) = materials.get(slot) {
+                primitive["material"] = material.into();
+            }
+            primitive
+        })
+        .collect::<Vec<Value>>();
+
+    gltf.meshes.push(json!({
+        "name": object_name(geometry),
+        "primitives": primitives,
+    }));
+    Ok(Some(gltf.meshes.len() - 1))
+}
+
+//----------------------------------------
+
+/// The 4 strongest influences, normalized
+fn vertex_weights(weights: Option<&Vec<(u16, f32)>>) -> ([u16; 4], [f32; 4]) {
+    let mut sorted = weights.cloned().unwrap_or_default();
+    sorted.sort_by(|a, b| b.1.total_cmp(&a.1));
+    sorted.truncate(4);
+
+    let total = sorted.iter().map(|(_, weight)| weight).sum::<f32>();
+    let mut joints = [0; 4];
+    let mut values = [0.0; 4];
+    for (i, (joint, weight)) in sorted.into_iter().enumerate() {
+        joints[i] = joint;
+        values[i] = if total > 0.0 { weight / total } else { 0.0 };
+    }
+    if total <= 0.0 {
+        values[0] = 1.0;
+    }
+    (joints, values)
+}
+
+//----------------------------------------
+
+#[derive(Default)]
+struct Gltf {
+    nodes: Vec<Value>,
+    meshes: Vec<Value>,
+    materials: Vec<Value>,
+    textures: Vec<Value>,
+    images: Vec<Value>,
+    skins: Vec<Value>,
+    animations: Vec<Value>,
+    accessors: Vec<Value>,
+    buffer_views: Vec<Value>,
+    bin: Vec<u8>,
+}
+
+//----------------------------------------
+
+impl Gltf {
+    fn view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        while !self.bin.len().is_multiple_of(4) {
+            self.bin.push(0);
+        }
+
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.bin.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = target.into();
+        }
+
+        self.bin.extend_from_slice(bytes);
+        self.buffer_views.push(view);
+        self.buffer_views.len() - 1
+    }
+
+    fn accessor(&mut self, data: &[f32], kind: &str, target: Option<u32>, bounds: bool) -> usize {
+        let width = component_count(kind);
+        let bytes = data
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<u8>>();
+        let view = self.view(&bytes, target);
+
+        let mut accessor = json!({
+            "bufferView": view,
+            "componentType": FLOAT,
+            "count": data.len() / width,
+            "type": kind,
+        });
+
+        if bounds {
+            let mut min = vec![f32::INFINITY; width];
+            let mut max = vec![f32::NEG_INFINITY; width];
+            for chunk in data.chunks_exact(width) {
+                for (i, &v) in chunk.iter().enumerate() {
+                    min[i] = min[i].min(v);
+                    max[i] = max[i].max(v);
+                }
+            }
+            accessor["min"] = json!(min);
+            accessor["max"] = json!(max);
+        }
+
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn joints_accessor(&mut self, data: &[u16]) -> usize {
+        let bytes = data
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<u8>>();
+        let view = self.view(&bytes, Some(ARRAY_BUFFER));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_SHORT,
+            "count": data.len() / 4,
+            "type": "VEC4",
+        }));
+        self.accessors.len() - 1
+    }
+
+    fn indices_accessor(&mut self, data: &[u32]) -> usize {
+        let bytes = data
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<u8>>();
+        let view = self.view(&bytes, Some(ELEMENT_ARRAY_BUFFER));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": data.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+
+    fn glb(mut self, root: usize) -> Vec<u8> {
+        while !self.bin.len().is_multiple_of(4) {
+            self.bin.push(0);
+        }
+
+        let mut json = Map::new();
+        json.insert(
+            "asset".into(),
+            json!({ "version": "2.0", "generator": env!("CARGO_PKG_NAME") }),
+        );
+        json.insert("scene".into(), 0.into());
+        json.insert("scenes".into(), json!([{ "nodes": [root] }]));
+
+        // glTF wants arrays left out rather than empty
+        for (name, values) in [
+            ("nodes", self.nodes),
+            ("meshes", self.meshes),
+            ("materials", self.materials),
+            ("textures", self.textures),
+            ("images", self.images),
+            ("skins", self.skins),
+            ("animations", self.animations),
+            ("accessors", self.accessors),
+            ("bufferViews", self.buffer_views),
+        ] {
+            if !values.is_empty() {
+                json.insert(name.into(), Value::Array(values));
+            }
+        }
+        if !self.bin.is_empty() {
+            json.insert("buffers".into(), json!([{ "byteLength": self.bin.len() }]));
+        }
+
+        let mut json = serde_json::to_vec(&Value::Object(json)).unwrap_or_default();
+        while !json.len().is_multiple_of(4) {
+            json.push(b' ');
+        }
+
+        let bin_chunk = if self.bin.is_empty() {
+            0
+        } else {
+            8 + self.bin.len()
+        };
+        let length = 12 + 8 + json.len() + bin_chunk;
+
+        let mut glb = Vec::with_capacity(length);
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(length as u32).to_le_bytes());
+
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+
+        if !self.bin.is_empty() {
+            glb.extend_from_slice(&(self.bin.len() as u32).to_le_bytes());
+            glb.extend_from_slice(b"BIN\0");
+            glb.extend_from_slice(&self.bin);
+        }
+
+        glb
+    }
+}
+
+//----------------------------------------
+
+fn component_count(kind: &str) -> usize {
+    match kind {
+        "VEC2" => 2,
+        "VEC3" => 3,
+        "VEC4" => 4,
+        "MAT4" => 16,
+        _ => 1,
+    }
+}
+
+//----------------------------------------
+
+/// Column major, the layout of both FBX and glTF matrices
+fn matrix(property: Option<&Property>) -> [f64; 16] {
+    let mut out = IDENTITY;
+    if let Some(values) = property.and_then(Property::to_f64_vec) {
+        if values.len() == 16 {
+            out.copy_from_slice(&values);
+        }
+    }
+    out
+}
+
+//----------------------------------------
+
+const IDENTITY: [f64; 16] = [
+    1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+];
+
+//----------------------------------------
+
+fn mat4_mul(a: &[f64; 16], b: &[f64; 16]) -> [f64; 16] {
+    let mut out = [0.0; 16];
+    for column in 0..4 {
+        for row in 0..4 {
+            out[column * 4 + row] = (0..4).map(|k| a[k * 4 + row] * b[column * 4 + k]).sum();
+        }
+    }
+    out
+}
+
+//----------------------------------------
+
+/// Gauss-Jordan, identity for singular matrices
+fn mat4_inverse(m: &[f64; 16]) -> [f64; 16] {
+    // Row major working copy, [m | I]
+    let mut a = [[0.0; 8]; 4];
+    for (row, line) in a.iter_mut().enumerate() {
+        for column in 0..4 {
+            line[column] = m[column * 4 + row];
+        }
+        line[4 + row] = 1.0;
+    }
+
+    for column in 0..4 {
+        let pivot = (column..4)
+            .max_by(|&x, &y| a[x][column].abs().total_cmp(&a[y][column].abs()))
+            .unwrap();
+        if a[pivot][column].abs() < 1e-12 {
+            return IDENTITY;
+        }
+        a.swap(column, pivot);
+
+        let scale = a[column][column];
+        for v in a[column].iter_mut() {
+            *v /= scale;
+        }
+        for row in 0..4 {
+            if row != column {
+                let factor = a[row][column];
+                let pivot_row = a[column];
+                for (v, p) in a[row].iter_mut().zip(pivot_row) {
+                    *v -= factor * p;
+                }
+            }
+        }
+    }
+
+    let mut out = [0.0; 16];
+    for (row, line) in a.iter().enumerate() {
+        for column in 0..4 {
+            out[column * 4 + row] = line[4 + column];
+        }
+    }
+    out
+}
+
+//----------------------------------------
+
+/// Euler degrees to quaternion [x, y, z, w]. `order` is FBX's RotationOrder,
+/// 0 = XYZ (X applied first) through 5 = ZYX.
+fn euler_quat(degrees: [f64; 3], order: usize) -> [f64; 4] {
+    let axis = |i: usize| {
+        let half = degrees[i].to_radians() / 2.0;
+        let mut q = [0.0, 0.0, 0.0, half.cos()];
+        q[i] = half.sin();
+        q
+    };
+
+    let sequence = match order {
+        1 => [0, 2, 1],
+        2 => [1, 2, 0],
+        3 => [1, 0, 2],
+        4 => [2, 0, 1],
+        5 => [2, 1, 0],
+        _ => [0, 1, 2],
+    };
+
+    sequence
+        .iter()
+        .fold([0.0, 0.0, 0.0, 1.0], |q, &i| quat_mul(axis(i), q))
+}
+
+//----------------------------------------
+
+fn quat_mul(a: [f64; 4], b: [f64; 4]) -> [f64; 4] {
+    [
+        a[3] * b[0] + a[0] * b[3] + a[1] * b[2] - a[2] * b[1],
+        a[3] * b[1] - a[0] * b[2] + a[1] * b[3] + a[2] * b[0],
+        a[3] * b[2] + a[0] * b[1] - a[1] * b[0] + a[2] * b[3],
+        a[3] * b[3] - a[0] * b[0] - a[1] * b[1] - a[2] * b[2],
+    ]
+}
+
+//----------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINARY: &[u8] = include_bytes!("../tests/fixtures/quad_binary.fbx");
+    const ASCII: &[u8] = include_bytes!("../tests/fixtures/quad_ascii.fbx");
+
+    /// The JSON chunk and the length of the BIN chunk
+    fn chunks(glb: &[u8]) -> (Value, usize) {
+        let u32_at = |at: usize| u32::from_le_bytes(glb[at..at + 4].try_into().unwrap()) as usize;
+
+        assert_eq!(&glb[..4], b"glTF");
+        assert_eq!(u32_at(4), 2);
+        assert_eq!(u32_at(8), glb.len());
+
+        let json_len = u32_at(12);
+        assert_eq!(&glb[16..20], b"JSON");
+        let json = serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+
+        let bin = 20 + json_len;
+        assert_eq!(&glb[bin + 4..bin + 8], b"BIN\0");
+        assert_eq!(bin + 8 + u32_at(bin), glb.len());
+        (json, u32_at(bin))
+    }
+
+    #[test]
+    fn a_quad_converts_to_two_triangles() {
+        for data in [BINARY, ASCII] {
+            let (json, bin_len) = chunks(&fbx_to_glb(data).unwrap());
+
+            // 4 positions, normals and UVs, and 6 indices
+            assert_eq!(bin_len, 4 * 12 + 4 * 12 + 4 * 8 + 6 * 4);
+            assert_eq!(json["buffers"][0]["byteLength"], bin_len);
+
+            let primitives = json["meshes"][0]["primitives"].as_array().unwrap();
+            assert_eq!(primitives.len(), 1);
+
+            let count = |accessor: &Value| {
+                json["accessors"][accessor.as_u64().unwrap() as usize]["count"].as_u64()
+            };
+            let attributes = &primitives[0]["attributes"];
+            assert_eq!(count(&attributes["POSITION"]), Some(4));
+            assert_eq!(count(&attributes["NORMAL"]), Some(4));
+            assert_eq!(count(&attributes["TEXCOORD_0"]), Some(4));
+            assert_eq!(count(&primitives[0]["indices"]), Some(6));
+
+            let position = &json["accessors"][attributes["POSITION"].as_u64().unwrap() as usize];
+            assert_eq!(position["min"], json!([-1.0, -1.0, 0.0]));
+            assert_eq!(position["max"], json!([1.0, 1.0, 0.0]));
+
+            assert_eq!(primitives[0]["material"], 0);
+            assert_eq!(
+                json["materials"][0]["pbrMetallicRoughness"]["baseColorFactor"],
+                json!([1.0, 0.0, 0.0, 1.0])
+            );
+
+            let nodes = json["nodes"].as_array().unwrap();
+            assert_eq!(nodes.len(), 2);
+            assert_eq!(nodes[0]["name"], "Quad");
+            assert_eq!(nodes[0]["mesh"], 0);
+            assert_eq!(nodes[0]["translation"], json!([1.0, 2.0, 3.0]));
+            assert_eq!(nodes[1]["name"], "RootNode");
+            assert_eq!(nodes[1]["children"], json!([0]));
+        }
+    }
+
+    #[test]
+    fn files_without_objects_are_malformed() {
+        let result = fbx_to_glb(b"; FBX 7.4.0 project file\nConnections:  {\n}\n");
+        assert!(matches!(result, Err(Error::MalformedFbx(_))));
+    }
+
+    #[test]
+    fn control_points_out_of_range_are_malformed() {
+        let ascii = String::from_utf8_lossy(ASCII);
+
+        // Past the 4 vertices, and far enough that * 3 overflows
+        for indices in ["a: 0,1,2,-9", "a: 0,6148914691236517206,2,-4"] {
+            let data = ascii.replace("a: 0,1,2,-4", indices);
+            assert!(matches!(
+                fbx_to_glb(data.as_bytes()),
+                Err(Error::MalformedFbx(_))
+            ));
+        }
+    }
+}
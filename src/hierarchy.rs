@@ -0,0 +1,244 @@
+//----------------------------------------
+
+use std::collections::{BTreeMap, HashSet};
+
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::unity_yaml::{reference_file_id, serialize_file_id, UnityDocument};
+
+//----------------------------------------
+
+/// A GameObject and its Transform, with child GameObjects nested
+#[derive(Debug, Clone, Serialize)]
+pub struct GameObjectNode {
+    /// 0 when the GameObject is not in the asset
+    #[serde(serialize_with = "serialize_file_id")]
+    pub file_id: i64,
+    pub name: Option<String>,
+    pub active: Option<bool>,
+    /// The GameObject or its Transform is a stripped prefab instance object
+    pub stripped: bool,
+    pub transform: LocalTransform,
+    pub components: Vec<ComponentRef>,
+    pub children: Vec<GameObjectNode>,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LocalTransform {
+    #[serde(serialize_with = "serialize_file_id")]
+    pub file_id: i64,
+    /// "Transform" or "RectTransform"
+    pub r#type: String,
+    pub position: Option<[f64; 3]>,
+    /// Quaternion x, y, z, w
+    pub rotation: Option<[f64; 4]>,
+    pub scale: Option<[f64; 3]>,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentRef {
+    #[serde(serialize_with = "serialize_file_id")]
+    pub file_id: i64,
+    /// None when the component is not in the asset
+    pub class_id: Option<i32>,
+    pub r#type: Option<String>,
+    /// `m_Script` guid of a MonoBehaviour
+    pub script_guid: Option<String>,
+}
+
+//----------------------------------------
+
+/// Rebuild the GameObject tree of a `.prefab` or `.unity` asset from the
+/// `m_Father`, `m_Children`, `m_GameObject`, and `m_Component` links.
+/// Returns the root GameObjects, in document order.
+pub fn build(documents: &[UnityDocument]) -> Vec<GameObjectNode> {
+    let by_file_id = documents
+        .iter()
+        .map(|document| (document.file_id, document))
+        .collect::<BTreeMap<i64, &UnityDocument>>();
+
+    let transforms = documents
+        .iter()
+        .filter(|document| is_transform(document))
+        .collect::<Vec<&UnityDocument>>();
+
+    // m_Father links, used for children missing from m_Children
+    let mut fathers = BTreeMap::<i64, Vec<i64>>::new();
+    for transform in &transforms {
+        let father = link(transform, "m_Father").unwrap_or(0);
+        fathers.entry(father).or_default().push(transform.file_id);
+    }
+
+    // Stripped transforms have no m_Father, only their parent's m_Children
+    // says they aren't roots
+    let listed_children = transforms
+        .iter()
+        .flat_map(|transform| child_file_ids(transform))
+        .collect::<HashSet<i64>>();
+
+    let mut seen = HashSet::new();
+    transforms
+        .iter()
+        .filter(|transform| {
+            let father = link(transform, "m_Father").unwrap_or(0);
+            !listed_children.contains(&transform.file_id)
+                && !by_file_id
+                    .get(&father)
+                    .is_some_and(|father| is_transform(father))
+        })
+        .filter_map(|transform| node(transform, &by_file_id, &fathers, &mut seen))
+        .collect()
+}
+
+//----------------------------------------
+
+fn node(
+    transform: &UnityDocument,
+    by_file_id: &BTreeMap<i64, &UnityDocument>,
+    fathers: &BTreeMap<i64, Vec<i64>>,
+    seen: &mut HashSet<i64>,
+) -> Option<GameObjectNode> {
+    // Guard against m_Father/m_Children cycles
+    if !seen.insert(transform.file_id) {
+        return None;
+    }
+
+    let game_object = link(transform, "m_GameObject").and_then(|id| by_file_id.get(&id));
+
+    let mut child_ids = child_file_ids(transform);
+    for id in fathers.get(&transform.file_id).into_iter().flatten() {
+        if !child_ids.contains(id) {
+            child_ids.push(*id);
+        }
+    }
+
+    let children = child_ids
+        .iter()
+        .filter_map(|id| by_file_id.get(id))
+        .filter(|child| is_transform(child))
+        .filter_map(|child| node(child, by_file_id, fathers, seen))
+        .collect();
+
+    Some(GameObjectNode {
+        file_id: game_object.map(|go| go.file_id).unwrap_or(0),
+        name: game_object
+            .and_then(|go| go.body.get("m_Name"))
+            .and_then(Value::as_str)
+            .map(|name| name.to_owned()),
+        active: game_object
+            .and_then(|go| go.body.get("m_IsActive"))
+            .and_then(Value::as_i64)
+            .map(|active| active != 0),
+        stripped: transform.stripped || game_object.is_some_and(|go| go.stripped),
+        transform: local_transform(transform),
+        components: game_object
+            .map(|go| components(go, by_file_id))
+            .unwrap_or_default(),
+        children,
+    })
+}
+
+//----------------------------------------
+
+fn child_file_ids(transform: &UnityDocument) -> Vec<i64> {
+    transform
+        .body
+        .get("m_Children")
+        .and_then(Value::as_sequence)
+        .map(|children| {
+            children
+                .iter()
+                .filter_map(reference_file_id)
+                .collect::<Vec<i64>>()
+        })
+        .unwrap_or_default()
+}
+
+//----------------------------------------
+
+fn components(
+    game_object: &UnityDocument,
+    by_file_id: &BTreeMap<i64, &UnityDocument>,
+) -> Vec<ComponentRef> {
+    let list = match game_object
+        .body
+        .get("m_Component")
+        .and_then(Value::as_sequence)
+    {
+        Some(list) => list,
+        None => return Vec::new(),
+    };
+
+    list.iter()
+        .filter_map(component_file_id)
+        .map(|file_id| {
+            let document = by_file_id.get(&file_id);
+            ComponentRef {
+                file_id,
+                class_id: document.map(|document| document.class_id),
+                r#type: document.map(|document| document.type_name.clone()),
+                script_guid: document
+                    .and_then(|document| document.body.get("m_Script"))
+                    .and_then(|script| script.get("guid"))
+                    .and_then(Value::as_str)
+                    .map(|guid| guid.to_owned()),
+            }
+        })
+        .collect()
+}
+
+//----------------------------------------
+
+fn local_transform(transform: &UnityDocument) -> LocalTransform {
+    let body = &transform.body;
+    LocalTransform {
+        file_id: transform.file_id,
+        r#type: transform.type_name.clone(),
+        position: vector(body.get("m_LocalPosition"), ["x", "y", "z"]),
+        rotation: vector(body.get("m_LocalRotation"), ["x", "y", "z", "w"]),
+        scale: vector(body.get("m_LocalScale"), ["x", "y", "z"]),
+    }
+}
+
+//----------------------------------------
+
+fn vector<const N: usize>(value: Option<&Value>, keys: [&str; N]) -> Option<[f64; N]> {
+    let value = value?;
+    let mut out = [0.0; N];
+    for (out, key) in out.iter_mut().zip(keys) {
+        *out = value.get(key)?.as_f64()?;
+    }
+    Some(out)
+}
+
+//----------------------------------------
+
+/// "- component: {fileID: X}", or "- 4: {fileID: X}" before 2018
+pub(crate) fn component_file_id(item: &Value) -> Option<i64> {
+    item.get("component")
+        .or_else(|| item.as_mapping()?.values().next())
+        .and_then(reference_file_id)
+}
+
+//----------------------------------------
+
+pub fn is_transform(document: &UnityDocument) -> bool {
+    matches!(document.class_id, 4 | 224)
+}
+
+//----------------------------------------
+
+fn link(document: &UnityDocument, key: &str) -> Option<i64> {
+    document
+        .body
+        .get(key)
+        .and_then(reference_file_id)
+        .filter(|&id| id != 0)
+}
+
+//----------------------------------------
@@ -0,0 +1,308 @@
+//----------------------------------------
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+use xz2::read::XzDecoder;
+use zip::ZipArchive;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+use crate::error::{Error, Result};
+use crate::package::{check_path, read_zip_file, Package, PackageType, ReadLimits};
+
+//----------------------------------------
+
+/// guid -> location of its files within the decompressed tar, built with a
+/// single pass over the package and saved next to it as a sidecar file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PackageIndex {
+    /// Size and modification time of the package the index was built from
+    pub package_size: u64,
+    pub package_modified: u64,
+    pub guids: BTreeMap<String, IndexEntry>,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub pathname: Option<String>,
+    /// "asset", "asset.meta", "pathname", etc
+    pub files: BTreeMap<String, EntryLocation>,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct EntryLocation {
+    pub offset: u64,
+    pub size: u64,
+}
+
+//----------------------------------------
+
+impl PackageIndex {
+    pub fn build(package: &Package) -> Result<PackageIndex> {
+        let (package_size, package_modified) = package_stamp(package)?;
+        let mut index = PackageIndex {
+            package_size,
+            package_modified,
+            guids: BTreeMap::new(),
+        };
+
+        for file in package.clone().open()?.entries()? {
+            let mut file = file?;
+
+            let guid = file.guid()?;
+            if guid.len() < 32 {
+                continue;
+            }
+
+            let file_name = file.file_name()?;
+            if file_name.is_empty() {
+                continue;
+            }
+
+            let location = EntryLocation {
+                offset: file.raw_file_position().unwrap_or(0),
+                size: file.size()? as u64,
+            };
+
+            let entry = index.guids.entry(guid).or_default();
+
+            if file_name == "pathname" {
+                let mut s = String::new();
+                file.read_to_string(&mut s)?;
+                entry.pathname = Some(s.split('\n').next().unwrap_or("").to_owned());
+            }
+
+            entry.files.insert(file_name, location);
+        }
+
+        Ok(index)
+    }
+
+    /// `<package>.index.json`, next to the package file or folder
+    pub fn sidecar_path(package_path: &Path) -> PathBuf {
+        let mut name = package_path
+            .file_name()
+            .map(|name| name.to_owned())
+            .unwrap_or_default();
+        name.push(".index.json");
+        package_path.with_file_name(name)
+    }
+
+    pub fn load(path: &Path) -> Result<PackageIndex> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        Ok(fs::write(path, serde_json::to_vec(self)?)?)
+    }
+
+    /// Load the sidecar index if it exists and still matches the package
+    pub fn load_sidecar(package: &Package) -> Result<Option<PackageIndex>> {
+        let path = PackageIndex::sidecar_path(package.path());
+        if package.in_memory() || !path.is_file() {
+            return Ok(None);
+        }
+
+        // An unreadable sidecar is no worse than a missing one, scan instead
+        let Ok(index) = PackageIndex::load(&path) else {
+            return Ok(None);
+        };
+        if index.is_fresh(package)? {
+            if let Some(limits) = package.limits() {
+                index.check(limits)?;
+            }
+            Ok(Some(index))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The hardened checks of `Package::hardened`, the sidecar is read
+    /// instead of the entries
+    pub fn check(&self, limits: ReadLimits) -> Result<()> {
+        for (guid, entry) in &self.guids {
+            check_path(guid)?;
+            if let Some(pathname) = &entry.pathname {
+                check_path(pathname)?;
+            }
+            for (file_name, location) in &entry.files {
+                check_path(file_name)?;
+                if location.size > limits.max_entry_size {
+                    return Err(Error::EntryTooLarge(format!(
+                        "{}/{} is {} bytes, the limit is {}",
+                        guid, file_name, location.size, limits.max_entry_size
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_fresh(&self, package: &Package) -> Result<bool> {
+        Ok(package_stamp(package)? == (self.package_size, self.package_modified))
+    }
+
+    pub fn read(&self, package: &Package, guid: &str, file_name: &str) -> Result<Vec<u8>> {
+        let wanted = [(guid.to_owned(), file_name.to_owned())];
+        Ok(self.read_many(package, &wanted)?.remove(0))
+    }
+
+    /// Read several `(guid, file_name)` entries, returned in the same order.
+    /// Tar, Zip, and Folder packages seek straight to each entry, compressed
+    /// tars are decompressed once, stopping after the last wanted entry.
+    pub fn read_many(
+        &self,
+        package: &Package,
+        wanted: &[(String, String)],
+    ) -> Result<Vec<Vec<u8>>> {
+        let locations = wanted
+            .iter()
+            .map(|(guid, file_name)| {
+                self.guids
+                    .get(guid)
+                    .and_then(|entry| entry.files.get(file_name))
+                    .copied()
+                    .ok_or_else(|| Error::MissingGuid(format!("{}/{}", guid, file_name)))
+            })
+            .collect::<Result<Vec<EntryLocation>>>()?;
+
+        match &package.r#type {
+            PackageType::Folder(root) => wanted
+                .iter()
+                .map(|(guid, file_name)| Ok(fs::read(root.join(guid).join(file_name))?))
+                .collect(),
+            PackageType::Tar(_) => {
+                let mut file = package.source()?;
+                locations
+                    .iter()
+                    .map(|location| {
+                        file.seek(SeekFrom::Start(location.offset))?;
+                        read_exact_vec(&mut file, location.size)
+                    })
+                    .collect()
+            }
+            PackageType::TarGz(_) => read_stream(GzDecoder::new(package.source()?), &locations),
+            PackageType::Zstd(_) => read_stream(ZstdDecoder::new(package.source()?)?, &locations),
+            PackageType::Xz(_) => {
+                read_stream(XzDecoder::new_multi_decoder(package.source()?), &locations)
+            }
+            PackageType::Bzip2(_) => {
+                read_stream(MultiBzDecoder::new(package.source()?), &locations)
+            }
+            PackageType::Zip(_) => {
+                let mut archive = ZipArchive::new(package.source()?)?;
+                wanted
+                    .iter()
+                    .map(|(guid, file_name)| {
+                        let name = format!("{}/{}", guid, file_name);
+                        let file = archive.by_name(&name)?;
+                        let size = file.size();
+                        let mut buffer = Vec::new();
+                        read_zip_file(file, &name, size, package.limits(), &mut buffer)?;
+                        Ok(buffer)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+//----------------------------------------
+
+/// Decompress once, skipping to each wanted entry in offset order
+fn read_stream<R: Read>(mut decoder: R, locations: &[EntryLocation]) -> Result<Vec<Vec<u8>>> {
+    let mut position = 0u64;
+
+    let mut order = (0..locations.len()).collect::<Vec<usize>>();
+    order.sort_by_key(|&i| locations[i].offset);
+
+    let mut by_offset = HashMap::<u64, Vec<u8>>::new();
+    for i in order {
+        let location = locations[i];
+        if by_offset.contains_key(&location.offset) {
+            continue;
+        }
+
+        let skip = location.offset.checked_sub(position).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "index entries overlap at offset {}, run index again",
+                    location.offset
+                ),
+            )
+        })?;
+        io::copy(&mut (&mut decoder).take(skip), &mut io::sink())?;
+        by_offset.insert(
+            location.offset,
+            read_exact_vec(&mut decoder, location.size)?,
+        );
+        position = location.offset + location.size;
+    }
+
+    Ok(locations
+        .iter()
+        .map(|location| by_offset[&location.offset].clone())
+        .collect())
+}
+
+//----------------------------------------
+
+/// The size comes from the sidecar, so the buffer only grows as data arrives
+fn read_exact_vec<R: Read>(reader: &mut R, size: u64) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    reader.take(size).read_to_end(&mut buffer)?;
+    if (buffer.len() as u64) < size {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(buffer)
+}
+
+//----------------------------------------
+
+/// Size and modification time, packages in memory only have a size
+fn package_stamp(package: &Package) -> Result<(u64, u64)> {
+    if package.in_memory() {
+        return Ok((package.archive_size()?.unwrap_or(0), 0));
+    }
+
+    // A folder's own time doesn't change when a file inside it is edited,
+    // sum the file sizes and take the newest time of everything below it
+    if let PackageType::Folder(root) = &package.r#type {
+        let (mut size, mut modified) = (0, 0);
+        for entry in WalkDir::new(root) {
+            let metadata = entry?.metadata()?;
+            if metadata.is_file() {
+                size += metadata.len();
+            }
+            modified = modified.max(modified_secs(&metadata)?);
+        }
+        return Ok((size, modified));
+    }
+
+    let metadata = fs::metadata(package.path())?;
+    Ok((metadata.len(), modified_secs(&metadata)?))
+}
+
+//----------------------------------------
+
+fn modified_secs(metadata: &fs::Metadata) -> Result<u64> {
+    Ok(metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0))
+}
+
+//----------------------------------------
@@ -1,12 +1,19 @@
 //----------------------------------------
 
 mod commands;
-mod package;
 
 //----------------------------------------
 
 use clap::{Parser, Subcommand};
+use std::io::Write;
 use std::path::PathBuf;
+use std::time::Duration;
+
+use unitypackage_util::fbx2gltf::Fbx2Gltf;
+use unitypackage_util::merge::{GuidConflict, PathnameConflict};
+use unitypackage_util::package::ReadLimits;
+use unitypackage_util::texture::TextureFormat;
+use unitypackage_util::{Error, Result};
 
 //----------------------------------------
 
@@ -26,9 +33,21 @@ use std::path::PathBuf;
     subcommand_required = true
 )]
 struct Cli {
-    /// Unity Package (Tar, TarGz, or Folder)
+    /// Unity Package (Tar, TarGz, Zstd, Xz, Bzip2, Zip, or Folder), - for stdin
     package: PathBuf,
 
+    /// Reject absolute and .. paths, links, devices, and oversized entries
+    #[arg(long, global = true)]
+    hardened: bool,
+
+    /// Largest entry in bytes, implies --hardened [default: 1 GiB]
+    #[arg(long, global = true, value_name = "BYTES")]
+    max_entry_size: Option<u64>,
+
+    /// Largest sum of entries in bytes, implies --hardened [default: 16 GiB]
+    #[arg(long, global = true, value_name = "BYTES")]
+    max_total_size: Option<u64>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -38,19 +57,39 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     // Test,
-    /// Find source of dump crashes
+    /// Report documents that fail to parse
     Debug,
-    /// Show package info
-    Info,
-    /// Display path from guid/pathname file
-    Name {
-        guid: String,
+    /// Show package info and statistics
+    Info {
+        /// Output JSON
+        #[arg(short, long)]
+        json: bool,
+
+        /// Pretty Print JSON
+        #[arg(short, long)]
+        pretty: bool,
     },
+    /// Build GUID index sidecar for faster name/extract
+    Index,
+    /// Display path from guid/pathname file
+    Name { guid: String },
     /// Dump package contents
     Dump {
         /// Pretty Print JSON
         #[arg(short, long)]
         pretty: bool,
+
+        /// Skip documents that fail to parse, listing them in "errors"
+        #[arg(short, long)]
+        recover: bool,
+
+        /// Annotate MonoBehaviours with the path and class of their script
+        #[arg(short = 's', long)]
+        resolve_scripts: bool,
+
+        /// Mark which assets have a preview.png thumbnail
+        #[arg(long)]
+        preview: bool,
     },
     /// List package contents
     List {
@@ -65,16 +104,93 @@ enum Commands {
         /// Directory Filter
         #[arg(short, long)]
         dir: Option<String>,
+
+        /// Add whether each asset has a preview.png thumbnail
+        #[arg(long)]
+        preview: bool,
+    },
+    /// Show GUID references between YAML assets
+    Deps {
+        /// Only show this asset and what it depends on
+        guid: Option<String>,
+
+        /// Output Graphviz DOT instead of JSON
+        #[arg(long)]
+        dot: bool,
+
+        /// Pretty Print JSON
+        #[arg(short, long)]
+        pretty: bool,
+    },
+    /// Report references to missing GUIDs and fileIDs
+    Check {
+        /// Pretty Print JSON
+        #[arg(short, long)]
+        pretty: bool,
+    },
+    /// Report structural problems, failing on errors
+    Validate {
+        /// Pretty Print JSON
+        #[arg(short, long)]
+        pretty: bool,
+    },
+    /// Compare with a newer package by GUID
+    Diff {
+        /// The newer package (Tar, TarGz, Zstd, Xz, Bzip2, Zip, or Folder)
+        other: PathBuf,
+
+        /// List changed documents and fields of changed YAML assets
+        #[arg(short, long)]
+        yaml: bool,
+
+        /// Pretty Print JSON
+        #[arg(short, long)]
+        pretty: bool,
+    },
+    /// Count YAML documents per Unity class ID
+    Classes {
+        /// Pretty Print JSON
+        #[arg(short, long)]
+        pretty: bool,
+    },
+    /// Show the GameObject tree of a prefab or scene
+    Hierarchy {
+        guid: String,
+
+        /// Resolve prefab instances against prefabs in the package
+        #[arg(short, long)]
+        flatten: bool,
+
+        /// Pretty Print JSON
+        #[arg(short, long)]
+        pretty: bool,
     },
     /// Extract package file
     Extract {
-        #[arg(required = true)]
-        guid: Option<String>,
+        /// GUIDs to extract, selecting several outputs a JSON object keyed by GUID
+        #[arg(required_unless_present_any = ["guids_from", "glob", "regex"])]
+        guids: Vec<String>,
+
+        /// Read GUIDs, one per line, from file or - for stdin
+        #[arg(long)]
+        guids_from: Option<PathBuf>,
+
+        /// Select assets by pathname glob
+        #[arg(long)]
+        glob: Option<glob::Pattern>,
 
-        /// Extract to file
+        /// Select assets by pathname regex
+        #[arg(long)]
+        regex: Option<regex::Regex>,
+
+        /// Extract to file, or into directory if it exists or ends with /
         #[arg(short, long)]
         output_file: Option<PathBuf>,
 
+        /// Overwrite existing output file
+        #[arg(long)]
+        force: bool,
+
         /// Extract /asset.meta file instead of /asset
         #[arg(short, long)]
         meta: bool,
@@ -87,19 +203,102 @@ enum Commands {
         #[arg(short, long)]
         pretty: bool,
 
-        /// Convert FBX to GLTF
+        /// Convert FBX to GLB, in-process when built with the native-fbx feature
         #[arg(short, long)]
         fbx2gltf: bool,
 
+        /// FBX2glTF binary, otherwise looked up next to this executable, in the working directory, then PATH
+        #[arg(long, env = "FBX2GLTF", value_name = "PATH")]
+        fbx2gltf_path: Option<PathBuf>,
+
+        /// Extra FBX2glTF argument, repeatable, e.g. --fbx2gltf-arg=--khr-materials-unlit
+        #[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+        fbx2gltf_arg: Vec<String>,
+
+        /// Kill FBX2glTF after this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        fbx2gltf_timeout: Option<u64>,
+
         /// Base64 encode output
         #[arg(short, long)]
         base64: bool,
+
+        /// With --json, annotate MonoBehaviours with the path and class of their script
+        #[arg(short = 's', long)]
+        resolve_scripts: bool,
+
+        /// Re-encode PSD, TGA, TIF, EXR, HDR, etc textures as png, webp, or ktx2, following the asset.meta TextureImporter
+        #[arg(long, value_name = "FORMAT")]
+        convert_texture: Option<TextureFormat>,
+
+        /// Extract /preview.png thumbnail instead of /asset
+        #[arg(long, conflicts_with_all = ["meta", "json"])]
+        preview: bool,
+    },
+    /// Extract all package files into a Unity project layout
+    #[command(alias = "extract-all")]
+    Unpack {
+        /// Output folder
+        output_dir: PathBuf,
+
+        /// Directory Filter
+        #[arg(short, long)]
+        dir: Option<String>,
+
+        /// List files that would be written
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Overwrite existing files
+        #[arg(long)]
+        force: bool,
+    },
+    /// Write preview.png thumbnails into a folder, named by pathname
+    Thumbnails {
+        /// Output folder
+        output_dir: PathBuf,
+
+        /// Directory Filter
+        #[arg(short, long)]
+        dir: Option<String>,
+
+        /// List files that would be written
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Create package from a Unity project folder
+    Pack {
+        /// Unity project folder
+        project: PathBuf,
+
+        /// Directory to pack, relative to the project
+        #[arg(short, long, default_value = "Assets")]
+        dir: String,
+    },
+    /// Merge packages into a new package at <PACKAGE>
+    Merge {
+        /// Packages to merge (any format <PACKAGE> accepts), earlier ones take precedence
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Same GUID with different content (by hash): prefer-first, prefer-newest-mtime
+        /// (latest file modification time), or fail
+        #[arg(long, value_name = "POLICY", default_value = "prefer-first")]
+        guid_conflict: GuidConflict,
+
+        /// Different GUIDs with the same pathname: rename or fail
+        #[arg(long, value_name = "POLICY", default_value = "rename")]
+        path_conflict: PathnameConflict,
+
+        /// Overwrite existing output
+        #[arg(short, long)]
+        force: bool,
     },
     /// Calculate xxhash 64 of string
     XxHash {
         #[arg(required = true)]
         text: String,
-    }
+    },
 }
 
 //----------------------------------------
@@ -107,47 +306,125 @@ enum Commands {
 fn main() {
     let cli = Cli::parse();
 
-    let package_path = cli.package.to_str().unwrap();
+    if let Err(err) = run(&cli) {
+        let _ = std::io::stderr().write_all(format!("{}\n", err).as_bytes());
+        std::process::exit(exit_code(&err));
+    }
+}
 
-    match &cli.command {
-        &Some(Commands::Info) => {
-            println!(
-                "Package: {}",
-                package::Package::new(package_path).unwrap()
-            );
+//----------------------------------------
+
+fn exit_code(err: &Error) -> exitcode::ExitCode {
+    match err {
+        Error::Io(_) => exitcode::IOERR,
+        Error::NotAPackage(_) => exitcode::USAGE,
+        Error::MalformedYaml(_) => exitcode::DATAERR,
+        Error::MissingGuid(_) => exitcode::NOINPUT,
+        Error::BadMeta(_) => exitcode::CONFIG,
+        Error::ExternalTool(_) => exitcode::UNAVAILABLE,
+        Error::OutputExists(_) => exitcode::CANTCREAT,
+        Error::CheckFailed(_) => exitcode::DATAERR,
+        Error::MalformedFbx(_) => exitcode::DATAERR,
+        Error::BadTexture(_) => exitcode::DATAERR,
+        Error::MergeConflict(_) => exitcode::DATAERR,
+        Error::UnsafePath(_) => exitcode::DATAERR,
+        Error::UnsupportedEntry(_) => exitcode::DATAERR,
+        Error::EntryTooLarge(_) => exitcode::DATAERR,
+        Error::PackageTooLarge(_) => exitcode::DATAERR,
+    }
+}
+
+//----------------------------------------
+
+fn run(cli: &Cli) -> Result<()> {
+    let package_path = &*cli.package.to_string_lossy();
+
+    let hardened = cli.hardened || cli.max_entry_size.is_some() || cli.max_total_size.is_some();
+    let limits = hardened.then(|| {
+        let defaults = ReadLimits::default();
+        ReadLimits {
+            max_entry_size: cli.max_entry_size.unwrap_or(defaults.max_entry_size),
+            max_total_size: cli.max_total_size.unwrap_or(defaults.max_total_size),
         }
-        &Some(Commands::Dump { pretty }) => {
-            commands::package_contents_dump(package_path, pretty, false);
+    });
+
+    match &cli.command {
+        &Some(Commands::Info { json, pretty }) => {
+            commands::package_info(package_path, limits, json, pretty)
         }
+        &Some(Commands::Index) => commands::package_index(package_path, limits),
+        &Some(Commands::Dump {
+            pretty,
+            recover,
+            resolve_scripts,
+            preview,
+        }) => commands::package_contents_dump(
+            package_path,
+            limits,
+            pretty,
+            recover,
+            resolve_scripts,
+            preview,
+            false,
+        ),
         &Some(Commands::Name { ref guid }) => {
-            commands::package_contents_name(package_path, guid);
+            commands::package_contents_name(package_path, limits, guid)
         }
         Some(Commands::List {
             no_guid,
             pretty,
             dir,
+            preview,
         }) => {
-            commands::package_contents_list(package_path, dir, !*no_guid, *pretty);
+            commands::package_contents_list(package_path, limits, dir, !*no_guid, *preview, *pretty)
         }
         &Some(Commands::Extract {
-            ref guid,
+            ref guids,
+            ref guids_from,
+            ref glob,
+            ref regex,
             ref output_file,
+            force,
             meta,
             json,
             pretty,
             fbx2gltf,
+            ref fbx2gltf_path,
+            ref fbx2gltf_arg,
+            fbx2gltf_timeout,
             base64,
+            resolve_scripts,
+            convert_texture,
+            preview,
         }) => {
-            commands::package_file_extract(
-                package_path,
-                guid.as_ref().unwrap(),
-                output_file,
+            let selection = commands::ExtractSelection {
+                guids: guids.clone(),
+                guids_from: guids_from.clone(),
+                glob: glob.clone(),
+                regex: regex.clone(),
+            };
+            let options = commands::ExtractOptions {
+                output_file: output_file.clone(),
+                force,
                 meta,
                 json,
                 pretty,
                 fbx2gltf,
+                converter: Fbx2Gltf {
+                    program: fbx2gltf_path.clone(),
+                    args: fbx2gltf_arg.clone(),
+                    timeout: fbx2gltf_timeout.map(Duration::from_secs),
+                },
                 base64,
-            );
+                resolve_scripts,
+                convert_texture,
+                preview,
+            };
+
+            match selection.single_guid() {
+                Some(guid) => commands::package_file_extract(package_path, limits, guid, &options),
+                None => commands::package_batch_extract(package_path, limits, &selection, &options),
+            }
         }
         // &Some(Commands::Test) => {
         //     let package = package::Package::new(package_path);
@@ -162,13 +439,61 @@ fn main() {
         //         }
         //     }
         // }
-        &Some(Commands::Debug) => {
-            commands::package_contents_dump(package_path, false, true);
+        &Some(Commands::Deps {
+            ref guid,
+            dot,
+            pretty,
+        }) => commands::package_deps(package_path, limits, guid, dot, pretty),
+        &Some(Commands::Check { pretty }) => commands::package_check(package_path, limits, pretty),
+        &Some(Commands::Diff {
+            ref other,
+            yaml,
+            pretty,
+        }) => commands::package_diff(package_path, limits, other, yaml, pretty),
+        &Some(Commands::Validate { pretty }) => {
+            commands::package_validate(package_path, limits, pretty)
+        }
+        &Some(Commands::Classes { pretty }) => {
+            commands::package_classes(package_path, limits, pretty)
         }
-        &Some(Commands::XxHash { ref text }) => {
-            commands::xx_hash(text);
+        &Some(Commands::Hierarchy {
+            ref guid,
+            flatten,
+            pretty,
+        }) => commands::package_hierarchy(package_path, limits, guid, flatten, pretty),
+        &Some(Commands::Debug) => {
+            commands::package_contents_dump(package_path, limits, false, true, false, false, true)
         }
-        &None => (),
+        &Some(Commands::Unpack {
+            ref output_dir,
+            ref dir,
+            dry_run,
+            force,
+        }) => commands::package_unpack(package_path, limits, output_dir, dir, dry_run, force),
+        &Some(Commands::Thumbnails {
+            ref output_dir,
+            ref dir,
+            dry_run,
+        }) => commands::package_thumbnails(package_path, limits, output_dir, dir, dry_run),
+        &Some(Commands::Pack {
+            ref project,
+            ref dir,
+        }) => commands::package_pack(package_path, project, dir),
+        &Some(Commands::Merge {
+            ref inputs,
+            guid_conflict,
+            path_conflict,
+            force,
+        }) => commands::package_merge(
+            package_path,
+            limits,
+            inputs,
+            guid_conflict,
+            path_conflict,
+            force,
+        ),
+        &Some(Commands::XxHash { ref text }) => commands::xx_hash(text),
+        &None => Ok(()),
     }
 }
 
@@ -0,0 +1,595 @@
+//----------------------------------------
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::str::FromStr;
+
+use serde::Serialize;
+use xxhash_rust::xxh64;
+
+use crate::error::{Error, Result};
+use crate::package::Package;
+use crate::writer::PackageWriter;
+
+//----------------------------------------
+
+/// What to do when the same GUID is in more than one package
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuidConflict {
+    /// Keep the copy from the package listed first
+    PreferFirst,
+    /// Keep the copy whose files have the latest modification time, later
+    /// packages win ties. Hashes only tell whether copies differ, not which
+    /// one is newer.
+    PreferNewestMtime,
+    Fail,
+}
+
+//----------------------------------------
+
+impl FromStr for GuidConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "prefer-first" => Ok(GuidConflict::PreferFirst),
+            "prefer-newest-mtime" => Ok(GuidConflict::PreferNewestMtime),
+            "fail" => Ok(GuidConflict::Fail),
+            _ => Err(format!(
+                "{} is not one of prefer-first, prefer-newest-mtime, fail",
+                s
+            )),
+        }
+    }
+}
+
+//----------------------------------------
+
+/// What to do when different GUIDs share a pathname
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathnameConflict {
+    /// Number the later asset like Unity does, "Name 1.ext"
+    Rename,
+    Fail,
+}
+
+//----------------------------------------
+
+impl FromStr for PathnameConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rename" => Ok(PathnameConflict::Rename),
+            "fail" => Ok(PathnameConflict::Fail),
+            _ => Err(format!("{} is not one of rename, fail", s)),
+        }
+    }
+}
+
+//----------------------------------------
+
+/// How the conflicts were resolved
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MergeReport {
+    /// Assets written
+    pub assets: usize,
+    /// GUIDs whose copies differed, and the package that was kept
+    pub duplicates: Vec<Duplicate>,
+    /// Assets moved aside because the pathname was taken, and the children
+    /// of folders that were
+    pub renamed: Vec<Renamed>,
+    /// Folders dropped because a folder with that pathname was kept
+    pub merged_folders: Vec<String>,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Duplicate {
+    pub guid: String,
+    pub pathname: String,
+    pub kept: String,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Renamed {
+    pub guid: String,
+    pub from: String,
+    pub to: String,
+}
+
+//----------------------------------------
+
+/// One package's copy of a GUID
+#[derive(Debug, Default)]
+struct Candidate {
+    package: usize,
+    pathname: String,
+    asset: Option<u64>,
+    meta: Option<u64>,
+    has_preview: bool,
+    mtime: u64,
+}
+
+//----------------------------------------
+
+impl Candidate {
+    fn is_folder(&self) -> bool {
+        self.asset.is_none()
+    }
+
+    /// asset, asset.meta and preview.png files the copy has
+    fn files(&self) -> usize {
+        self.asset.is_some() as usize + self.meta.is_some() as usize + self.has_preview as usize
+    }
+
+    fn same_as(&self, other: &Candidate) -> bool {
+        self.pathname == other.pathname && self.asset == other.asset && self.meta == other.meta
+    }
+}
+
+//----------------------------------------
+
+/// Copy every asset of `packages` into `writer`. Copies of a GUID with the
+/// same content hash are not a conflict, and neither are folders that share a pathname, the
+/// first folder is kept and the others' children land in it.
+pub fn merge_packages<W: Write>(
+    packages: &[Package],
+    writer: &mut PackageWriter<W>,
+    guids: GuidConflict,
+    pathnames: PathnameConflict,
+) -> Result<MergeReport> {
+    let mut candidates = BTreeMap::<String, Vec<Candidate>>::new();
+    for (index, package) in packages.iter().enumerate() {
+        for (guid, candidate) in scan(package, index)? {
+            candidates.entry(guid).or_default().push(candidate);
+        }
+    }
+
+    let mut report = MergeReport::default();
+
+    // GUID -> the chosen copy
+    let mut chosen = BTreeMap::<String, Candidate>::new();
+    for (guid, copies) in candidates {
+        let kept = choose(&guid, copies, guids, packages, &mut report)?;
+        chosen.insert(guid, kept);
+    }
+
+    // Earlier packages claim pathnames first, then folders before files and
+    // parents before children, so children can follow a renamed folder
+    let mut order = chosen.iter().collect::<Vec<(&String, &Candidate)>>();
+    order.sort_by_key(|(_, c)| (c.package, !c.is_folder(), c.pathname.clone()));
+
+    // Unity projects are usually on case insensitive file systems
+    let mut taken = HashMap::<String, (String, bool)>::new();
+    let mut pathnames_out = BTreeMap::<String, String>::new();
+    let mut dropped = BTreeSet::<String>::new();
+    // Renamed folders, (package, from, to)
+    let mut moved = Vec::<(usize, String, String)>::new();
+
+    for (guid, candidate) in order {
+        // Children follow their folder when it was renamed
+        let wanted = moved_pathname(&candidate.pathname, candidate.package, &moved);
+        let pathname = match taken.get(&wanted.to_lowercase()) {
+            None => wanted,
+            Some((_, true)) if candidate.is_folder() => {
+                report.merged_folders.push(wanted);
+                dropped.insert(guid.clone());
+                continue;
+            }
+            Some((other, _)) => match pathnames {
+                PathnameConflict::Fail => {
+                    return Err(Error::MergeConflict(format!(
+                        "{} is both {} and {}",
+                        wanted, other, guid
+                    )))
+                }
+                PathnameConflict::Rename => free_pathname(&wanted, &taken),
+            },
+        };
+
+        if pathname != candidate.pathname {
+            if candidate.is_folder() {
+                moved.push((
+                    candidate.package,
+                    candidate.pathname.clone(),
+                    pathname.clone(),
+                ));
+            }
+            report.renamed.push(Renamed {
+                guid: guid.clone(),
+                from: candidate.pathname.clone(),
+                to: pathname.clone(),
+            });
+        }
+        taken.insert(
+            pathname.to_lowercase(),
+            (guid.clone(), candidate.is_folder()),
+        );
+        pathnames_out.insert(guid.clone(), pathname);
+    }
+
+    for (index, package) in packages.iter().enumerate() {
+        let wanted = chosen
+            .iter()
+            .filter(|(guid, c)| c.package == index && !dropped.contains(*guid))
+            .map(|(guid, c)| (guid.clone(), c.files()))
+            .collect::<HashMap<String, usize>>();
+        if wanted.is_empty() {
+            continue;
+        }
+
+        report.assets += copy_files(package, &wanted, &pathnames_out, writer)?;
+    }
+
+    Ok(report)
+}
+
+//----------------------------------------
+
+/// Hash each GUID's files without keeping them
+fn scan(package: &Package, index: usize) -> Result<BTreeMap<String, Candidate>> {
+    let mut candidates = BTreeMap::<String, Candidate>::new();
+
+    for file in package.clone().open()?.entries()? {
+        let mut file = file?;
+
+        let guid = file.guid()?;
+        if guid.len() < 32 {
+            continue;
+        }
+
+        let file_name = file.file_name()?;
+        if !matches!(
+            file_name.as_str(),
+            "pathname" | "asset" | "asset.meta" | "preview.png"
+        ) {
+            continue;
+        }
+
+        let candidate = candidates.entry(guid).or_insert_with(|| Candidate {
+            package: index,
+            ..Candidate::default()
+        });
+        if file_name == "preview.png" {
+            candidate.has_preview = true;
+            continue;
+        }
+
+        candidate.mtime = candidate.mtime.max(file.mtime()?);
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+
+        match file_name.as_str() {
+            "pathname" => {
+                let pathname = String::from_utf8_lossy(&buffer);
+                candidate.pathname = pathname.split('\n').next().unwrap_or("").to_owned();
+            }
+            "asset" => candidate.asset = Some(xxh64::xxh64(&buffer, 0)),
+            _ => candidate.meta = Some(xxh64::xxh64(&buffer, 0)),
+        }
+    }
+
+    Ok(candidates)
+}
+
+//----------------------------------------
+
+fn choose(
+    guid: &str,
+    mut copies: Vec<Candidate>,
+    policy: GuidConflict,
+    packages: &[Package],
+    report: &mut MergeReport,
+) -> Result<Candidate> {
+    if copies.iter().all(|copy| copy.same_as(&copies[0])) {
+        return Ok(copies.swap_remove(0));
+    }
+
+    let kept = match policy {
+        GuidConflict::PreferFirst => 0,
+        GuidConflict::PreferNewestMtime => {
+            // max_by_key returns the last of equal keys, the later package
+            copies
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, copy)| copy.mtime)
+                .map(|(i, _)| i)
+                .unwrap_or(0)
+        }
+        GuidConflict::Fail => {
+            return Err(Error::MergeConflict(format!(
+                "{} differs between {}",
+                guid,
+                copies
+                    .iter()
+                    .map(|copy| packages[copy.package].path().display().to_string())
+                    .collect::<Vec<String>>()
+                    .join(" and ")
+            )))
+        }
+    };
+
+    let kept = copies.swap_remove(kept);
+    report.duplicates.push(Duplicate {
+        guid: guid.to_owned(),
+        pathname: kept.pathname.clone(),
+        kept: packages[kept.package].path().display().to_string(),
+    });
+    Ok(kept)
+}
+
+//----------------------------------------
+
+/// `pathname` below the innermost folder of `package` that was renamed
+fn moved_pathname(pathname: &str, package: usize, moved: &[(usize, String, String)]) -> String {
+    moved
+        .iter()
+        .filter(|(from_package, _, _)| *from_package == package)
+        .filter_map(|(_, from, to)| {
+            let rest = pathname.strip_prefix(from.as_str())?.strip_prefix('/')?;
+            Some((from.len(), format!("{}/{}", to, rest)))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, pathname)| pathname)
+        .unwrap_or_else(|| pathname.to_owned())
+}
+
+//----------------------------------------
+
+/// "Assets/Rock.prefab" -> "Assets/Rock 1.prefab", "Assets/Rock 2.prefab", ..
+fn free_pathname(pathname: &str, taken: &HashMap<String, (String, bool)>) -> String {
+    let name_start = pathname.rfind('/').map(|i| i + 1).unwrap_or(0);
+    let (stem, ext) = match pathname[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => pathname.split_at(name_start + dot),
+        _ => (pathname, ""),
+    };
+
+    (1..)
+        .map(|n| format!("{} {}{}", stem, n, ext))
+        .find(|candidate| !taken.contains_key(&candidate.to_lowercase()))
+        .unwrap()
+}
+
+//----------------------------------------
+
+/// Write the `wanted` GUIDs of `package`, each as soon as its files are
+/// read, the files of a GUID are usually next to each other. Returns the
+/// number of GUIDs written.
+fn copy_files<W: Write>(
+    package: &Package,
+    wanted: &HashMap<String, usize>,
+    pathnames: &BTreeMap<String, String>,
+    writer: &mut PackageWriter<W>,
+) -> Result<usize> {
+    let mut pending = HashMap::<String, HashMap<String, Vec<u8>>>::new();
+    let mut written = 0;
+
+    for file in package.clone().open()?.entries()? {
+        let mut file = file?;
+
+        let guid = file.guid()?;
+        let Some(&count) = wanted.get(&guid) else {
+            continue;
+        };
+
+        let file_name = file.file_name()?;
+        if !matches!(file_name.as_str(), "asset" | "asset.meta" | "preview.png") {
+            continue;
+        }
+
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+
+        let files = pending.entry(guid.clone()).or_default();
+        files.insert(file_name, buffer);
+        if files.len() == count {
+            let files = pending.remove(&guid).unwrap();
+            write_asset(&guid, &pathnames[&guid], &files, writer)?;
+            written += 1;
+        }
+    }
+
+    // Only left when a GUID's files changed between the two passes
+    for (guid, files) in pending {
+        write_asset(&guid, &pathnames[&guid], &files, writer)?;
+        written += 1;
+    }
+
+    Ok(written)
+}
+
+//----------------------------------------
+
+fn write_asset<W: Write>(
+    guid: &str,
+    pathname: &str,
+    files: &HashMap<String, Vec<u8>>,
+    writer: &mut PackageWriter<W>,
+) -> Result<()> {
+    let meta = files.get("asset.meta").map(Vec::as_slice).unwrap_or(b"");
+    writer.add_asset(guid, pathname, files.get("asset").map(Vec::as_slice), meta)?;
+    if let Some(preview) = files.get("preview.png") {
+        writer.add_preview(guid, preview)?;
+    }
+    Ok(())
+}
+
+//----------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{entry, guid, meta, package, tar};
+
+    /// (guid, pathname, asset, mtime), folders have no asset
+    type Asset<'a> = (&'a str, &'a str, Option<&'a str>, u64);
+
+    /// guid -> (pathname, asset) of the merged package
+    type Merged = BTreeMap<String, (String, Option<String>)>;
+
+    fn build(assets: &[Asset]) -> Package {
+        let mut entries = Vec::new();
+        for (guid, pathname, asset, mtime) in assets {
+            let mut meta = meta(guid);
+            if asset.is_none() {
+                meta.push_str("folderAsset: yes\n");
+            }
+            entries.push(entry(format!("{}/pathname", guid), pathname).mtime(*mtime));
+            entries.push(entry(format!("{}/asset.meta", guid), meta).mtime(*mtime));
+            if let Some(asset) = asset {
+                entries.push(entry(format!("{}/asset", guid), asset).mtime(*mtime));
+            }
+        }
+        package(&tar(&entries))
+    }
+
+    fn merge(
+        packages: &[Package],
+        guids: GuidConflict,
+        pathnames: PathnameConflict,
+    ) -> Result<(MergeReport, Merged)> {
+        let mut writer = PackageWriter::new(Vec::new());
+        let report = merge_packages(packages, &mut writer, guids, pathnames)?;
+        let merged = package(&writer.finish()?);
+
+        let mut assets = Merged::new();
+        for file in merged.open()?.entries()? {
+            let mut file = file?;
+            let mut data = String::new();
+            file.read_to_string(&mut data)?;
+            let asset = assets.entry(file.guid()?).or_default();
+            match file.file_name()?.as_str() {
+                "pathname" => asset.0 = data,
+                "asset" => asset.1 = Some(data),
+                _ => (),
+            }
+        }
+        Ok((report, assets))
+    }
+
+    fn asset(pathname: &str, data: Option<&str>) -> (String, Option<String>) {
+        (pathname.to_owned(), data.map(|data| data.to_owned()))
+    }
+
+    //----------------------------------------
+
+    #[test]
+    fn identical_copies_are_not_a_conflict() {
+        let a = guid(1);
+        let first = build(&[(&a, "Assets/A.txt", Some("a"), 1)]);
+        let second = build(&[(&a, "Assets/A.txt", Some("a"), 2)]);
+
+        let (report, assets) =
+            merge(&[first, second], GuidConflict::Fail, PathnameConflict::Fail).unwrap();
+
+        assert!(report.duplicates.is_empty());
+        assert_eq!(assets[&a], asset("Assets/A.txt", Some("a")));
+    }
+
+    #[test]
+    fn guid_conflict_policies() {
+        let a = guid(1);
+        let packages = [
+            build(&[(&a, "Assets/A.txt", Some("old"), 20)]),
+            build(&[(&a, "Assets/A.txt", Some("new"), 10)]),
+            build(&[(&a, "Assets/A.txt", Some("tie"), 20)]),
+        ];
+
+        let (report, assets) =
+            merge(&packages, GuidConflict::PreferFirst, PathnameConflict::Fail).unwrap();
+        assert_eq!(report.duplicates.len(), 1);
+        assert_eq!(assets[&a].1.as_deref(), Some("old"));
+
+        // Later packages win ties
+        let (_, assets) = merge(
+            &packages,
+            GuidConflict::PreferNewestMtime,
+            PathnameConflict::Fail,
+        )
+        .unwrap();
+        assert_eq!(assets[&a].1.as_deref(), Some("tie"));
+
+        let (_, assets) = merge(
+            &packages[..2],
+            GuidConflict::PreferNewestMtime,
+            PathnameConflict::Fail,
+        )
+        .unwrap();
+        assert_eq!(assets[&a].1.as_deref(), Some("old"));
+
+        assert!(matches!(
+            merge(&packages, GuidConflict::Fail, PathnameConflict::Fail),
+            Err(Error::MergeConflict(_))
+        ));
+    }
+
+    #[test]
+    fn pathname_conflicts_rename_ignoring_case() {
+        let (a, b, c) = (guid(1), guid(2), guid(3));
+        let packages = [
+            build(&[(&a, "Assets/Rock.prefab", Some("a"), 0)]),
+            build(&[
+                (&b, "Assets/rock.prefab", Some("b"), 0),
+                (&c, "Assets/Rock.prefab", Some("c"), 0),
+            ]),
+        ];
+
+        let (report, assets) =
+            merge(&packages, GuidConflict::Fail, PathnameConflict::Rename).unwrap();
+
+        assert_eq!(report.renamed.len(), 2);
+        assert_eq!(assets[&a].0, "Assets/Rock.prefab");
+        // Within a package in pathname order
+        assert_eq!(assets[&c].0, "Assets/Rock 1.prefab");
+        assert_eq!(assets[&b].0, "Assets/rock 2.prefab");
+
+        assert!(matches!(
+            merge(&packages, GuidConflict::Fail, PathnameConflict::Fail),
+            Err(Error::MergeConflict(_))
+        ));
+    }
+
+    #[test]
+    fn folders_with_the_same_pathname_are_merged() {
+        let (a, b, c) = (guid(1), guid(2), guid(3));
+        let packages = [
+            build(&[(&a, "Assets/Art", None, 0)]),
+            build(&[
+                (&b, "Assets/Art", None, 0),
+                (&c, "Assets/Art/C.txt", Some("c"), 0),
+            ]),
+        ];
+
+        let (report, assets) =
+            merge(&packages, GuidConflict::Fail, PathnameConflict::Fail).unwrap();
+
+        assert_eq!(report.merged_folders, vec!["Assets/Art".to_owned()]);
+        assert!(!assets.contains_key(&b));
+        assert_eq!(assets[&a], asset("Assets/Art", None));
+        assert_eq!(assets[&c], asset("Assets/Art/C.txt", Some("c")));
+    }
+
+    #[test]
+    fn children_follow_a_renamed_folder() {
+        let (a, b, c, d) = (guid(1), guid(2), guid(3), guid(4));
+        let packages = [
+            build(&[(&a, "Assets/Art", Some("a file"), 0)]),
+            build(&[
+                (&b, "Assets/Art", None, 0),
+                (&c, "Assets/Art/Sub", None, 0),
+                (&d, "Assets/Art/Sub/D.txt", Some("d"), 0),
+            ]),
+        ];
+
+        let (_, assets) = merge(&packages, GuidConflict::Fail, PathnameConflict::Rename).unwrap();
+
+        assert_eq!(assets[&a].0, "Assets/Art");
+        assert_eq!(assets[&b].0, "Assets/Art 1");
+        assert_eq!(assets[&c].0, "Assets/Art 1/Sub");
+        assert_eq!(assets[&d].0, "Assets/Art 1/Sub/D.txt");
+    }
+}
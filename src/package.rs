@@ -1,250 +1,955 @@
 //----------------------------------------
 
-use std::borrow::BorrowMut;
+use std::cell::RefCell;
 use std::fs::{self, File};
-use std::io::Read;
-use std::path::PathBuf;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 
+use bzip2::read::MultiBzDecoder;
 use flate2::read::GzDecoder;
 use tar::Archive;
 use walkdir::WalkDir;
+use xz2::read::XzDecoder;
+use zip::ZipArchive;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+use crate::error::{Error, Result};
+
+//----------------------------------------
+
+/// Path of packages read from stdin or memory
+pub const STDIN_PATH: &str = "-";
 
 //----------------------------------------
 
+#[derive(Clone)]
 pub struct Package {
-    r#type: PackageType,
+    pub(crate) r#type: PackageType,
+    /// Set by `hardened`
+    pub(crate) limits: Option<ReadLimits>,
+    /// The whole archive, for packages read from stdin or memory
+    pub(crate) data: Option<Arc<[u8]>>,
 }
 
 //----------------------------------------
 
-enum PackageType {
+#[derive(Clone)]
+pub(crate) enum PackageType {
     Folder(PathBuf),
     Tar(PathBuf),
     TarGz(PathBuf),
+    Zstd(PathBuf),
+    Xz(PathBuf),
+    Bzip2(PathBuf),
+    Zip(PathBuf),
+}
+
+//----------------------------------------
+
+/// Size limits of the hardened reading mode, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadLimits {
+    /// Largest single entry
+    pub max_entry_size: u64,
+    /// Largest sum of all entries, the decompressed size of a TarGz
+    pub max_total_size: u64,
+}
+
+//----------------------------------------
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        ReadLimits {
+            max_entry_size: 1 << 30,
+            max_total_size: 16 << 30,
+        }
+    }
+}
+
+//----------------------------------------
+
+/// Archive bytes, from a file or from memory
+pub(crate) enum Source {
+    File(File),
+    Memory(Cursor<Arc<[u8]>>),
+}
+
+//----------------------------------------
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::File(file) => file.read(buf),
+            Source::Memory(cursor) => cursor.read(buf),
+        }
+    }
+}
+
+impl Seek for Source {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Source::File(file) => file.seek(pos),
+            Source::Memory(cursor) => cursor.seek(pos),
+        }
+    }
 }
 
 //----------------------------------------
 
 enum PackageFileSystemHandle {
     Folder(String, Option<WalkDir>),
-    Tar(Archive<File>),
-    TarGz(Archive<GzDecoder<File>>),
+    Tar(Box<Archive<Source>>),
+    TarGz(Box<Archive<GzDecoder<Source>>>),
+    Zstd(Box<Archive<ZstdDecoder<'static, BufReader<Source>>>>),
+    Xz(Box<Archive<XzDecoder<Source>>>),
+    Bzip2(Box<Archive<MultiBzDecoder<Source>>>),
+    Zip(Box<RefCell<ZipArchive<Source>>>),
+}
+
+//----------------------------------------
+
+pub struct PackageEntries<'a> {
+    entries: Entries<'a>,
+    limits: Option<ReadLimits>,
+    /// Sum of the entry sizes so far, checked against the limits
+    total_size: u64,
 }
 
 //----------------------------------------
 
-pub enum PackageEntries<'a> {
+enum Entries<'a> {
     Folder(
         String,
-        Option<Box<dyn Iterator<Item = Result<walkdir::DirEntry, walkdir::Error>>>>,
+        Option<Box<dyn Iterator<Item = std::result::Result<walkdir::DirEntry, walkdir::Error>>>>,
     ),
-    Tar(Result<tar::Entries<'a, File>, std::io::Error>),
-    TarGz(Result<tar::Entries<'a, GzDecoder<File>>, std::io::Error>),
+    Tar(tar::Entries<'a, Source>),
+    TarGz(tar::Entries<'a, GzDecoder<Source>>),
+    Zstd(tar::Entries<'a, ZstdDecoder<'static, BufReader<Source>>>),
+    Xz(tar::Entries<'a, XzDecoder<Source>>),
+    Bzip2(tar::Entries<'a, MultiBzDecoder<Source>>),
+    /// Zip entries are read by index, one at a time
+    Zip(&'a RefCell<ZipArchive<Source>>, Range<usize>),
 }
 
 //----------------------------------------
 
-pub enum PackageEntry<'a> {
+pub struct PackageEntry<'a> {
+    entry: Entry<'a>,
+    /// Check pathname contents when read, and cap zip reads
+    limits: Option<ReadLimits>,
+}
+
+//----------------------------------------
+
+enum Entry<'a> {
     Folder(String, walkdir::DirEntry),
-    Tar(tar::Entry<'a, File>),
-    TarGz(tar::Entry<'a, GzDecoder<File>>),
+    Tar(tar::Entry<'a, Source>),
+    TarGz(tar::Entry<'a, GzDecoder<Source>>),
+    Zstd(tar::Entry<'a, ZstdDecoder<'static, BufReader<Source>>>),
+    Xz(tar::Entry<'a, XzDecoder<Source>>),
+    Bzip2(tar::Entry<'a, MultiBzDecoder<Source>>),
+    Zip(&'a RefCell<ZipArchive<Source>>, usize, ZipInfo),
+}
+
+//----------------------------------------
+
+/// Read up front, the archive is only borrowed while reading data
+struct ZipInfo {
+    name: String,
+    size: u64,
+    mtime: u64,
+    is_dir: bool,
+    is_symlink: bool,
 }
 
 //----------------------------------------
 
 pub struct PackageHandle {
     handle: PackageFileSystemHandle,
+    limits: Option<ReadLimits>,
 }
 
 //----------------------------------------
 
 impl core::fmt::Display for Package {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self.r#type {
-            PackageType::Folder(path) => write!(f, "Folder: {}", path.display()),
-            PackageType::Tar(path) => write!(f, "Tar: {}", path.display()),
-            PackageType::TarGz(path) => write!(f, "TarGz: {}", path.display()),
-        }
+        write!(f, "{}: {}", self.kind(), self.path().display())
     }
 }
 
 //----------------------------------------
 
 impl Package {
-    pub fn new(path: &str) -> Result<Package, String> {
+    pub fn new(path: &str) -> Result<Package> {
         let path = PathBuf::from(path);
 
-        if path.is_dir() {
-            Ok(Package {
-                // canonicalize to fix any path issues
-                r#type: PackageType::Folder(path.canonicalize().unwrap()),
-            })
+        let r#type = if path.is_dir() {
+            // canonicalize to fix any path issues
+            PackageType::Folder(path.canonicalize()?)
         } else if path.is_file() {
-            let kind = infer::get_from_path(&path).unwrap().unwrap();
-            match kind.mime_type() {
-                "application/x-tar" => Ok(Package {
-                    r#type: PackageType::Tar(path),
-                }),
-                "application/gzip" => Ok(Package {
-                    r#type: PackageType::TarGz(path),
-                }),
-                _ => Err("File is not a tar or tar.gz".into()),
-            }
+            archive_type(infer::get_from_path(&path)?, path)?
         } else {
-            Err("Path is not a file or directory".into())
+            return Err(Error::NotAPackage(format!(
+                "{} is not a file or directory",
+                path.display()
+            )));
+        };
+
+        Ok(Package {
+            r#type,
+            limits: None,
+            data: None,
+        })
+    }
+
+    /// Read a whole archive, sniffing the compression from its header. The
+    /// path of the package is `-`.
+    pub fn from_reader(mut reader: impl Read) -> Result<Package> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Package::from_data(data.into())
+    }
+
+    /// An archive in memory, sniffing the compression from its header. The
+    /// path of the package is `-`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Package> {
+        Package::from_data(bytes.into())
+    }
+
+    fn from_data(data: Arc<[u8]>) -> Result<Package> {
+        Ok(Package {
+            r#type: archive_type(infer::get(&data), PathBuf::from(STDIN_PATH))?,
+            limits: None,
+            data: Some(data),
+        })
+    }
+
+    /// Read from stdin or memory rather than a path
+    pub fn in_memory(&self) -> bool {
+        self.data.is_some()
+    }
+
+    /// Size of the archive, None for folders
+    pub fn archive_size(&self) -> Result<Option<u64>> {
+        match (&self.r#type, &self.data) {
+            (PackageType::Folder(_), _) => Ok(None),
+            (_, Some(data)) => Ok(Some(data.len() as u64)),
+            (_, None) => Ok(Some(fs::metadata(self.path())?.len())),
+        }
+    }
+
+    /// The archive bytes, folders have none
+    pub(crate) fn source(&self) -> Result<Source> {
+        match &self.data {
+            Some(data) => Ok(Source::Memory(Cursor::new(data.clone()))),
+            None => Ok(Source::File(File::open(self.path())?)),
+        }
+    }
+
+    /// Reject absolute and `..` paths, in entry names and pathname files,
+    /// links and devices, and entries over the size limits
+    pub fn hardened(mut self, limits: ReadLimits) -> Package {
+        self.limits = Some(limits);
+        self
+    }
+
+    pub fn limits(&self) -> Option<ReadLimits> {
+        self.limits
+    }
+
+    pub fn path(&self) -> &Path {
+        match &self.r#type {
+            PackageType::Folder(path)
+            | PackageType::Tar(path)
+            | PackageType::TarGz(path)
+            | PackageType::Zstd(path)
+            | PackageType::Xz(path)
+            | PackageType::Bzip2(path)
+            | PackageType::Zip(path) => path,
         }
     }
 
-    pub fn open(self) -> Result<PackageHandle, String> {
-        match self.r#type {
+    /// "Folder", "Tar", "TarGz", "Zstd", "Xz", "Bzip2", or "Zip"
+    pub fn kind(&self) -> &'static str {
+        match &self.r#type {
+            PackageType::Folder(_) => "Folder",
+            PackageType::Tar(_) => "Tar",
+            PackageType::TarGz(_) => "TarGz",
+            PackageType::Zstd(_) => "Zstd",
+            PackageType::Xz(_) => "Xz",
+            PackageType::Bzip2(_) => "Bzip2",
+            PackageType::Zip(_) => "Zip",
+        }
+    }
+
+    pub fn open(self) -> Result<PackageHandle> {
+        let handle = match &self.r#type {
             PackageType::Folder(path) => {
-                let dir = WalkDir::new(&path);
-                Ok(PackageHandle {
-                    handle: PackageFileSystemHandle::Folder(
-                        path.to_str().unwrap().to_string(),
-                        Some(dir),
-                    ),
-                })
+                let dir = WalkDir::new(path);
+                PackageFileSystemHandle::Folder(path.to_string_lossy().to_string(), Some(dir))
             }
-            PackageType::Tar(path) => {
-                let file = File::open(&path).unwrap();
-                let archive = Archive::new(file);
-                Ok(PackageHandle {
-                    handle: PackageFileSystemHandle::Tar(archive),
-                })
+            PackageType::Tar(_) => {
+                PackageFileSystemHandle::Tar(Box::new(Archive::new(self.source()?)))
             }
-            PackageType::TarGz(path) => {
-                let file = File::open(&path).unwrap();
-                let file = GzDecoder::new(file);
-
-                let archive = Archive::new(file);
-                Ok(PackageHandle {
-                    handle: PackageFileSystemHandle::TarGz(archive),
-                })
+            PackageType::TarGz(_) => {
+                let file = GzDecoder::new(self.source()?);
+                PackageFileSystemHandle::TarGz(Box::new(Archive::new(file)))
             }
-        }
+            PackageType::Zstd(_) => {
+                let file = ZstdDecoder::new(self.source()?)?;
+                PackageFileSystemHandle::Zstd(Box::new(Archive::new(file)))
+            }
+            PackageType::Xz(_) => {
+                // Multi stream, as written by parallel compressors
+                let file = XzDecoder::new_multi_decoder(self.source()?);
+                PackageFileSystemHandle::Xz(Box::new(Archive::new(file)))
+            }
+            PackageType::Bzip2(_) => {
+                let file = MultiBzDecoder::new(self.source()?);
+                PackageFileSystemHandle::Bzip2(Box::new(Archive::new(file)))
+            }
+            PackageType::Zip(_) => {
+                let archive = ZipArchive::new(self.source()?)?;
+                PackageFileSystemHandle::Zip(Box::new(RefCell::new(archive)))
+            }
+        };
+
+        Ok(PackageHandle {
+            handle,
+            limits: self.limits,
+        })
     }
 }
 
 //----------------------------------------
 
 impl PackageHandle {
-    pub fn entries(&mut self) -> PackageEntries {
-        match self.handle.borrow_mut() {
-            PackageFileSystemHandle::Folder(path, entries) => PackageEntries::Folder(
+    pub fn entries(&mut self) -> Result<PackageEntries<'_>> {
+        let entries = match &mut self.handle {
+            PackageFileSystemHandle::Folder(path, entries) => Entries::Folder(
                 path.clone(),
-                Some(Box::new(entries.take().unwrap().into_iter())),
+                entries
+                    .take()
+                    .map(|dir| Box::new(dir.into_iter()) as Box<dyn Iterator<Item = _>>),
             ),
-            PackageFileSystemHandle::Tar(archive) => PackageEntries::Tar(archive.entries()),
-            PackageFileSystemHandle::TarGz(archive) => PackageEntries::TarGz(archive.entries()),
-        }
+            PackageFileSystemHandle::Tar(archive) => Entries::Tar(archive.entries()?),
+            PackageFileSystemHandle::TarGz(archive) => Entries::TarGz(archive.entries()?),
+            PackageFileSystemHandle::Zstd(archive) => Entries::Zstd(archive.entries()?),
+            PackageFileSystemHandle::Xz(archive) => Entries::Xz(archive.entries()?),
+            PackageFileSystemHandle::Bzip2(archive) => Entries::Bzip2(archive.entries()?),
+            PackageFileSystemHandle::Zip(archive) => {
+                let len = archive.borrow().len();
+                Entries::Zip(archive, 0..len)
+            }
+        };
+
+        Ok(PackageEntries {
+            entries,
+            limits: self.limits,
+            total_size: 0,
+        })
     }
 }
 
 //----------------------------------------
 
 impl<'a> Iterator for PackageEntries<'a> {
-    type Item = std::io::Result<PackageEntry<'a>>;
-
-    fn next(&mut self) -> Option<std::io::Result<PackageEntry<'a>>> {
-        match self.borrow_mut() {
-            PackageEntries::Folder(path, entries) => {
-                let next = entries.as_mut().unwrap().next();
-                match next {
-                    Some(Ok(entry)) => Some(Ok(PackageEntry::Folder(path.clone(), entry))),
-                    Some(Err(err)) => Some(Err(err.into())),
-                    None => None,
-                }
-            }
-            PackageEntries::Tar(entries) => match entries.as_mut().unwrap().next() {
-                Some(Ok(entry)) => Some(Ok(PackageEntry::Tar(entry))),
-                Some(Err(err)) => Some(Err(err)),
-                None => None,
+    type Item = Result<PackageEntry<'a>>;
+
+    fn next(&mut self) -> Option<Result<PackageEntry<'a>>> {
+        let entry = match &mut self.entries {
+            Entries::Folder(path, entries) => match entries.as_mut()?.next()? {
+                Ok(entry) => Entry::Folder(path.clone(), entry),
+                Err(err) => return Some(Err(err.into())),
+            },
+            Entries::Tar(entries) => match entries.next()? {
+                Ok(entry) => Entry::Tar(entry),
+                Err(err) => return Some(Err(err.into())),
+            },
+            Entries::TarGz(entries) => match entries.next()? {
+                Ok(entry) => Entry::TarGz(entry),
+                Err(err) => return Some(Err(err.into())),
+            },
+            Entries::Zstd(entries) => match entries.next()? {
+                Ok(entry) => Entry::Zstd(entry),
+                Err(err) => return Some(Err(err.into())),
+            },
+            Entries::Xz(entries) => match entries.next()? {
+                Ok(entry) => Entry::Xz(entry),
+                Err(err) => return Some(Err(err.into())),
             },
-            PackageEntries::TarGz(entries) => match entries.as_mut().unwrap().next() {
-                Some(Ok(entry)) => Some(Ok(PackageEntry::TarGz(entry))),
-                Some(Err(err)) => Some(Err(err)),
-                None => None,
+            Entries::Bzip2(entries) => match entries.next()? {
+                Ok(entry) => Entry::Bzip2(entry),
+                Err(err) => return Some(Err(err.into())),
             },
+            Entries::Zip(archive, indices) => {
+                let index = indices.next()?;
+                match zip_info(archive, index) {
+                    Ok(info) => Entry::Zip(archive, index, info),
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+        };
+
+        let entry = PackageEntry {
+            entry,
+            limits: self.limits,
+        };
+
+        match self.limits {
+            Some(limits) => Some(self.check(entry, limits)),
+            None => Some(Ok(entry)),
+        }
+    }
+}
+
+//----------------------------------------
+
+impl<'a> PackageEntries<'a> {
+    fn check(&mut self, entry: PackageEntry<'a>, limits: ReadLimits) -> Result<PackageEntry<'a>> {
+        let path = entry.path()?;
+        check_path(&path.to_string_lossy())?;
+
+        if let Some(kind) = entry.unsupported_kind()? {
+            return Err(Error::UnsupportedEntry(format!(
+                "{} is a {}",
+                path.display(),
+                kind
+            )));
         }
+
+        let size = entry.size()? as u64;
+        if size > limits.max_entry_size {
+            return Err(Error::EntryTooLarge(format!(
+                "{} is {} bytes, the limit is {}",
+                path.display(),
+                size,
+                limits.max_entry_size
+            )));
+        }
+
+        self.total_size += size;
+        if self.total_size > limits.max_total_size {
+            return Err(Error::PackageTooLarge(format!(
+                "over {} bytes at {}",
+                limits.max_total_size,
+                path.display()
+            )));
+        }
+
+        Ok(entry)
     }
 }
 
+//----------------------------------------
+
 impl<'a> PackageEntry<'a> {
-    pub fn size(&self) -> Option<usize> {
-        match self {
-            PackageEntry::Folder(_path, entry) => Some(entry.metadata().unwrap().len() as usize),
-            PackageEntry::Tar(entry) => Some(entry.header().size().unwrap() as usize),
-            PackageEntry::TarGz(entry) => Some(entry.header().size().unwrap() as usize),
+    pub fn size(&self) -> Result<usize> {
+        match &self.entry {
+            Entry::Folder(_path, entry) => Ok(entry.metadata()?.len() as usize),
+            Entry::Tar(entry) => Ok(entry.header().size()? as usize),
+            Entry::TarGz(entry) => Ok(entry.header().size()? as usize),
+            Entry::Zstd(entry) => Ok(entry.header().size()? as usize),
+            Entry::Xz(entry) => Ok(entry.header().size()? as usize),
+            Entry::Bzip2(entry) => Ok(entry.header().size()? as usize),
+            Entry::Zip(_archive, _index, info) => Ok(info.size as usize),
         }
     }
 
-    pub fn path(&self) -> Option<PathBuf> {
-        match self {
-            PackageEntry::Folder(root_path, entry) => {
-                let path = entry.path().to_str().unwrap();
-                let replace = format!("{}/", root_path);
-                let path = path.replace(&replace, "");
-                // println!("path: {}", path);
-                Some(PathBuf::from(path))
-            }
-            PackageEntry::Tar(entry) => Some(PathBuf::from(entry.path().unwrap())),
-            PackageEntry::TarGz(entry) => Some(PathBuf::from(entry.path().unwrap())),
+    pub fn is_dir(&self) -> bool {
+        match &self.entry {
+            Entry::Folder(_path, entry) => entry.file_type().is_dir(),
+            Entry::Tar(entry) => entry.header().entry_type().is_dir(),
+            Entry::TarGz(entry) => entry.header().entry_type().is_dir(),
+            Entry::Zstd(entry) => entry.header().entry_type().is_dir(),
+            Entry::Xz(entry) => entry.header().entry_type().is_dir(),
+            Entry::Bzip2(entry) => entry.header().entry_type().is_dir(),
+            Entry::Zip(_archive, _index, info) => info.is_dir,
         }
     }
 
-    pub fn read_to_string(&mut self, buf: &mut String) -> std::io::Result<usize> {
-        match self {
-            PackageEntry::Folder(_path, entry) => {
-                let path = entry.path().to_str().unwrap();
-                let result = fs::read_to_string(path);
-                *buf = result.unwrap();
-                Ok(buf.len())
-            }
-            PackageEntry::Tar(entry) => entry.read_to_string(buf),
-            PackageEntry::TarGz(entry) => entry.read_to_string(buf),
+    /// Modification time in seconds since the epoch
+    pub fn mtime(&self) -> Result<u64> {
+        match &self.entry {
+            Entry::Folder(_path, entry) => Ok(entry
+                .metadata()?
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or(0)),
+            Entry::Tar(entry) => Ok(entry.header().mtime()?),
+            Entry::TarGz(entry) => Ok(entry.header().mtime()?),
+            Entry::Zstd(entry) => Ok(entry.header().mtime()?),
+            Entry::Xz(entry) => Ok(entry.header().mtime()?),
+            Entry::Bzip2(entry) => Ok(entry.header().mtime()?),
+            Entry::Zip(_archive, _index, info) => Ok(info.mtime),
         }
     }
 
-    pub fn read_to_end(&mut self, buf: &mut Vec<u8>) -> std::io::Result<usize> {
-        match self {
-            PackageEntry::Folder(_path, entry) => {
-                let path = entry.path().to_str().unwrap();
-                let result = fs::read(path);
-                *buf = result.unwrap();
-                Ok(buf.len())
+    pub fn path(&self) -> Result<PathBuf> {
+        match &self.entry {
+            Entry::Folder(root_path, entry) => {
+                let path = entry.path();
+                let path = path.strip_prefix(root_path).unwrap_or(path);
+                Ok(path.to_path_buf())
             }
-            PackageEntry::Tar(entry) => entry.read_to_end(buf),
-            PackageEntry::TarGz(entry) => entry.read_to_end(buf),
+            Entry::Tar(entry) => Ok(entry.path()?.to_path_buf()),
+            Entry::TarGz(entry) => Ok(entry.path()?.to_path_buf()),
+            Entry::Zstd(entry) => Ok(entry.path()?.to_path_buf()),
+            Entry::Xz(entry) => Ok(entry.path()?.to_path_buf()),
+            Entry::Bzip2(entry) => Ok(entry.path()?.to_path_buf()),
+            Entry::Zip(_archive, _index, info) => Ok(PathBuf::from(&info.name)),
         }
     }
 
-    pub fn guid(&self) -> String {
-        match self {
-            PackageEntry::Folder(root_path, entry) => {
-                let replace = format!("{}/", root_path);
-                let path = entry.path().to_str().unwrap();
-                let path = path.replace(&replace, "");
-                let path = path.split("/").collect::<Vec<&str>>();
-                let path = path.first().unwrap();
-                path.to_string()
+    pub fn read_to_string(&mut self, buf: &mut String) -> Result<usize> {
+        let limits = self.limits;
+        let size = match &mut self.entry {
+            Entry::Folder(_path, entry) => {
+                *buf = fs::read_to_string(entry.path())?;
+                buf.len()
+            }
+            Entry::Tar(entry) => entry.read_to_string(buf)?,
+            Entry::TarGz(entry) => entry.read_to_string(buf)?,
+            Entry::Zstd(entry) => entry.read_to_string(buf)?,
+            Entry::Xz(entry) => entry.read_to_string(buf)?,
+            Entry::Bzip2(entry) => entry.read_to_string(buf)?,
+            Entry::Zip(archive, index, info) => {
+                let mut data = Vec::new();
+                let mut archive = archive.borrow_mut();
+                let file = archive.by_index(*index)?;
+                let size = read_zip_file(file, &info.name, info.size, limits, &mut data)?;
+                buf.push_str(
+                    &String::from_utf8(data)
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+                );
+                size
             }
-            PackageEntry::Tar(entry) => {
-                let path = entry.path().unwrap();
-                let path = path.to_str().unwrap();
-                let path = path.split("/").collect::<Vec<&str>>();
-                let path = path.first().unwrap();
-                path.to_string()
+        };
+        self.check_pathname(buf.as_bytes())?;
+        Ok(size)
+    }
+
+    pub fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        let limits = self.limits;
+        let size = match &mut self.entry {
+            Entry::Folder(_path, entry) => {
+                *buf = fs::read(entry.path())?;
+                buf.len()
             }
-            PackageEntry::TarGz(entry) => {
-                let path = entry.path().unwrap();
-                let path = path.to_str().unwrap();
-                let path = path.split("/").collect::<Vec<&str>>();
-                let path = path.first().unwrap();
-                path.to_string()
+            Entry::Tar(entry) => entry.read_to_end(buf)?,
+            Entry::TarGz(entry) => entry.read_to_end(buf)?,
+            Entry::Zstd(entry) => entry.read_to_end(buf)?,
+            Entry::Xz(entry) => entry.read_to_end(buf)?,
+            Entry::Bzip2(entry) => entry.read_to_end(buf)?,
+            Entry::Zip(archive, index, info) => read_zip_file(
+                archive.borrow_mut().by_index(*index)?,
+                &info.name,
+                info.size,
+                limits,
+                buf,
+            )?,
+        };
+        self.check_pathname(buf)?;
+        Ok(size)
+    }
+
+    /// Read at most the first `len` bytes, for sniffing the content type
+    pub fn read_prefix(&mut self, len: u64, buf: &mut Vec<u8>) -> Result<usize> {
+        let size = match &mut self.entry {
+            Entry::Folder(_path, entry) => {
+                fs::File::open(entry.path())?.take(len).read_to_end(buf)?
             }
+            Entry::Tar(entry) => entry.take(len).read_to_end(buf)?,
+            Entry::TarGz(entry) => entry.take(len).read_to_end(buf)?,
+            Entry::Zstd(entry) => entry.take(len).read_to_end(buf)?,
+            Entry::Xz(entry) => entry.take(len).read_to_end(buf)?,
+            Entry::Bzip2(entry) => entry.take(len).read_to_end(buf)?,
+            Entry::Zip(archive, index, _info) => archive
+                .borrow_mut()
+                .by_index(*index)?
+                .take(len)
+                .read_to_end(buf)?,
+        };
+        Ok(size)
+    }
+
+    /// Offset of the entry data within the (decompressed) tar, None for folders
+    /// and zips
+    pub fn raw_file_position(&self) -> Option<u64> {
+        match &self.entry {
+            Entry::Folder(_path, _entry) => None,
+            Entry::Tar(entry) => Some(entry.raw_file_position()),
+            Entry::TarGz(entry) => Some(entry.raw_file_position()),
+            Entry::Zstd(entry) => Some(entry.raw_file_position()),
+            Entry::Xz(entry) => Some(entry.raw_file_position()),
+            Entry::Bzip2(entry) => Some(entry.raw_file_position()),
+            Entry::Zip(_archive, _index, _info) => None,
         }
     }
+
+    /// Path below the guid folder, "asset", "asset.meta", "pathname", etc
+    pub fn file_name(&self) -> Result<String> {
+        let path = self.path()?;
+        Ok(path
+            .components()
+            .skip(1)
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"))
+    }
+
+    pub fn guid(&self) -> Result<String> {
+        let path = self.path()?;
+        Ok(path
+            .components()
+            .next()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .unwrap_or_default())
+    }
+
+    /// Links, devices, etc, anything but files and directories
+    fn unsupported_kind(&self) -> Result<Option<String>> {
+        match &self.entry {
+            Entry::Folder(_path, entry) => {
+                let file_type = entry.file_type();
+                if file_type.is_symlink() {
+                    Ok(Some("symlink".to_owned()))
+                } else if file_type.is_file() || file_type.is_dir() {
+                    Ok(None)
+                } else {
+                    Ok(Some("special file".to_owned()))
+                }
+            }
+            Entry::Tar(entry) => Ok(unsupported_tar_kind(entry.header().entry_type())),
+            Entry::TarGz(entry) => Ok(unsupported_tar_kind(entry.header().entry_type())),
+            Entry::Zstd(entry) => Ok(unsupported_tar_kind(entry.header().entry_type())),
+            Entry::Xz(entry) => Ok(unsupported_tar_kind(entry.header().entry_type())),
+            Entry::Bzip2(entry) => Ok(unsupported_tar_kind(entry.header().entry_type())),
+            Entry::Zip(_archive, _index, info) => Ok(info.is_symlink.then(|| "symlink".to_owned())),
+        }
+    }
+
+    /// The pathname file is used to build output paths
+    fn check_pathname(&self, buf: &[u8]) -> Result<()> {
+        if self.limits.is_some() && self.file_name()? == "pathname" {
+            let pathname = String::from_utf8_lossy(buf);
+            check_path(pathname.split('\n').next().unwrap_or(""))?;
+        }
+        Ok(())
+    }
 }
 
 //----------------------------------------
+
+/// The archive format `infer` found, `path` only names it in errors
+fn archive_type(kind: Option<infer::Type>, path: PathBuf) -> Result<PackageType> {
+    match kind.map(|kind| kind.mime_type()) {
+        Some("application/x-tar") => Ok(PackageType::Tar(path)),
+        Some("application/gzip") => Ok(PackageType::TarGz(path)),
+        Some("application/zstd") => Ok(PackageType::Zstd(path)),
+        Some("application/x-xz") => Ok(PackageType::Xz(path)),
+        Some("application/x-bzip2") => Ok(PackageType::Bzip2(path)),
+        Some("application/zip") => Ok(PackageType::Zip(path)),
+        _ => Err(Error::NotAPackage(format!(
+            "{} is not a tar, tar.gz, tar.zst, tar.xz, tar.bz2, or zip",
+            path.display()
+        ))),
+    }
+}
+
+//----------------------------------------
+
+fn zip_info(archive: &RefCell<ZipArchive<Source>>, index: usize) -> Result<ZipInfo> {
+    let mut archive = archive.borrow_mut();
+    let file = archive.by_index_raw(index)?;
+    Ok(ZipInfo {
+        name: file.name().to_owned(),
+        size: file.size(),
+        mtime: file.last_modified().map(dos_time_to_unix).unwrap_or(0),
+        is_dir: file.is_dir(),
+        is_symlink: file.is_symlink(),
+    })
+}
+
+//----------------------------------------
+
+/// The checks only saw the size a zip declares, stop reading past it, or
+/// past the entry limit, so a zip bomb can't inflate any further
+pub(crate) fn read_zip_file(
+    file: impl Read,
+    name: &str,
+    declared_size: u64,
+    limits: Option<ReadLimits>,
+    buf: &mut Vec<u8>,
+) -> Result<usize> {
+    let cap = limits.map_or(declared_size, |limits| {
+        declared_size.min(limits.max_entry_size)
+    });
+    let size = file.take(cap + 1).read_to_end(buf)?;
+    if size as u64 > cap {
+        return Err(Error::EntryTooLarge(format!(
+            "{} inflates past {} bytes",
+            name, cap
+        )));
+    }
+    Ok(size)
+}
+
+//----------------------------------------
+
+/// Zip times are local time without a zone, read as UTC
+fn dos_time_to_unix(time: zip::DateTime) -> u64 {
+    // Days from civil, http://howardhinnant.github.io/date_algorithms.html
+    let (month, day) = (time.month() as i64, time.day() as i64);
+    let year = time.year() as i64 - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let seconds =
+        days * 86400 + time.hour() as i64 * 3600 + time.minute() as i64 * 60 + time.second() as i64;
+    seconds.max(0) as u64
+}
+
+//----------------------------------------
+
+fn unsupported_tar_kind(entry_type: tar::EntryType) -> Option<String> {
+    match entry_type {
+        // Global pax headers only carry metadata, `git archive` writes one
+        tar::EntryType::Regular | tar::EntryType::Directory | tar::EntryType::XGlobalHeader => None,
+        tar::EntryType::Symlink => Some("symlink".to_owned()),
+        tar::EntryType::Link => Some("hardlink".to_owned()),
+        tar::EntryType::Char | tar::EntryType::Block => Some("device".to_owned()),
+        tar::EntryType::Fifo => Some("fifo".to_owned()),
+        entry_type => Some(format!("{:?} entry", entry_type)),
+    }
+}
+
+//----------------------------------------
+
+/// Reject absolute paths and `..`, with either slash, so a path stays
+/// inside whatever directory it is joined to on any platform
+pub fn check_path(path: &str) -> Result<()> {
+    let unsafe_path = || Err(Error::UnsafePath(path.to_owned()));
+
+    let normalized = path.replace('\\', "/");
+    if normalized.starts_with('/') || normalized.split('/').any(|part| part == "..") {
+        return unsafe_path();
+    }
+
+    // "C:/..." and "C:..." are only absolute on Windows, reject them anywhere
+    if normalized.as_bytes().get(1) == Some(&b':') {
+        return unsafe_path();
+    }
+
+    if !Path::new(&normalized)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return unsafe_path();
+    }
+
+    Ok(())
+}
+
+//----------------------------------------
+
+/// `dir` joined with a path read from a package, checked whether or not the
+/// package is hardened since the result is written to
+pub fn safe_join(dir: &Path, path: &str) -> Result<PathBuf> {
+    if path.is_empty() {
+        return Err(Error::UnsafePath(path.to_owned()));
+    }
+    check_path(path)?;
+    Ok(dir.join(path))
+}
+
+//----------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::test_util::{entry, package, tar};
+
+    const GUID: &str = "0123456789abcdef0123456789abcdef";
+
+    //----------------------------------------
+
+    /// A zip with one asset that says it is `declared` bytes and inflates
+    /// to `actual`
+    fn zip_bomb(declared: u32, actual: usize) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file(
+                format!("{}/asset", GUID),
+                zip::write::SimpleFileOptions::default(),
+            )
+            .unwrap();
+        writer.write_all(&vec![0; actual]).unwrap();
+        let mut data = writer.finish().unwrap().into_inner();
+
+        // The uncompressed size of the local header and the central directory
+        for (signature, offset) in [(b"PK\x03\x04", 22), (b"PK\x01\x02", 24)] {
+            let start = data.windows(4).position(|w| w == signature).unwrap() + offset;
+            data[start..start + 4].copy_from_slice(&declared.to_le_bytes());
+        }
+        data
+    }
+
+    fn read_all(package: Package) -> Result<()> {
+        for entry in package.open()?.entries()? {
+            entry?.read_to_end(&mut Vec::new())?;
+        }
+        Ok(())
+    }
+
+    fn hardened(data: &[u8], max_entry_size: u64, max_total_size: u64) -> Package {
+        package(data).hardened(ReadLimits {
+            max_entry_size,
+            max_total_size,
+        })
+    }
+
+    fn read_first(package: Package) -> Result<Vec<u8>> {
+        let mut handle = package.open()?;
+        let mut entry = handle.entries()?.next().unwrap()?;
+        let mut buffer = Vec::new();
+        entry.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    //----------------------------------------
+
+    #[test]
+    fn zip_reads_stop_at_the_declared_size() {
+        let bomb = zip_bomb(10, 1 << 20);
+
+        assert!(matches!(
+            read_first(package(&bomb)),
+            Err(Error::EntryTooLarge(_))
+        ));
+
+        let package = package(&bomb).hardened(ReadLimits {
+            max_entry_size: 1000,
+            ..ReadLimits::default()
+        });
+        assert!(matches!(read_first(package), Err(Error::EntryTooLarge(_))));
+    }
+
+    #[test]
+    fn zip_reads_the_declared_size() {
+        let package = package(&zip_bomb(1000, 1000));
+        assert_eq!(read_first(package).unwrap(), vec![0; 1000]);
+    }
+
+    #[test]
+    fn read_prefix_stops_at_the_length() {
+        for data in [
+            tar(&[entry(format!("{}/asset", GUID), [1; 100])]),
+            zip_bomb(100, 100),
+        ] {
+            let mut handle = package(&data).open().unwrap();
+            let mut entry = handle.entries().unwrap().next().unwrap().unwrap();
+            let mut buffer = Vec::new();
+
+            assert_eq!(entry.read_prefix(10, &mut buffer).unwrap(), 10);
+            assert_eq!(buffer.len(), 10);
+        }
+    }
+
+    //----------------------------------------
+
+    #[test]
+    fn check_path_rejects_paths_leaving_the_directory() {
+        for path in [
+            "/etc/passwd",
+            "\\server\\share",
+            "..",
+            "Assets/../../escaped.txt",
+            "Assets\\..\\..\\escaped.txt",
+            "C:/Windows/escaped.txt",
+            "C:escaped.txt",
+        ] {
+            assert!(
+                matches!(check_path(path), Err(Error::UnsafePath(_))),
+                "{}",
+                path
+            );
+        }
+
+        for path in ["Assets/Rock.prefab", "./Assets/a..b.txt", GUID] {
+            assert!(check_path(path).is_ok(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn safe_join_stays_inside_the_directory() {
+        let dir = Path::new("out");
+
+        assert_eq!(
+            safe_join(dir, "Assets/Rock.prefab").unwrap(),
+            dir.join("Assets/Rock.prefab")
+        );
+        for path in ["", "/tmp/escaped.txt", "Assets/../../escaped.txt"] {
+            assert!(matches!(safe_join(dir, path), Err(Error::UnsafePath(_))));
+        }
+    }
+
+    #[test]
+    fn hardened_rejects_unsafe_entry_paths() {
+        let data = tar(&[entry("../escaped.txt", "x")]);
+
+        assert!(read_all(package(&data)).is_ok());
+        assert!(matches!(
+            read_all(hardened(&data, 100, 100)),
+            Err(Error::UnsafePath(_))
+        ));
+    }
+
+    #[test]
+    fn hardened_rejects_unsafe_pathname_contents() {
+        let data = tar(&[entry(format!("{}/pathname", GUID), "/tmp/escaped.txt\n")]);
+
+        assert!(matches!(
+            read_all(hardened(&data, 100, 100)),
+            Err(Error::UnsafePath(_))
+        ));
+    }
+
+    #[test]
+    fn hardened_rejects_links() {
+        for entry_type in [tar::EntryType::Symlink, tar::EntryType::Link] {
+            let data = tar(&[entry(format!("{}/asset", GUID), "").entry_type(entry_type)]);
+
+            assert!(matches!(
+                read_all(hardened(&data, 100, 100)),
+                Err(Error::UnsupportedEntry(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn hardened_rejects_oversized_entries() {
+        let data = tar(&[
+            entry(format!("{}/asset", GUID), [0; 60]),
+            entry(format!("{}/asset.meta", GUID), [0; 60]),
+        ]);
+
+        assert!(read_all(hardened(&data, 100, 200)).is_ok());
+        assert!(matches!(
+            read_all(hardened(&data, 50, 200)),
+            Err(Error::EntryTooLarge(_))
+        ));
+        assert!(matches!(
+            read_all(hardened(&data, 100, 100)),
+            Err(Error::PackageTooLarge(_))
+        ));
+    }
+}
@@ -0,0 +1,873 @@
+//----------------------------------------
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::error::Result;
+use crate::hierarchy::{component_file_id, is_transform};
+use crate::index::PackageIndex;
+use crate::package::Package;
+use crate::unity_yaml::{self, reference_file_id, serialize_file_id, UnityDocument};
+
+//----------------------------------------
+
+const PREFAB_EXTENSION: &str = ".prefab";
+
+//----------------------------------------
+
+/// Parsed `.prefab` assets of a package, by guid, the sources
+/// PrefabInstance documents are resolved against
+#[derive(Debug, Default)]
+pub struct PrefabSources {
+    pub prefabs: HashMap<String, Vec<UnityDocument>>,
+}
+
+//----------------------------------------
+
+/// A PrefabInstance that was left as is
+#[derive(Debug, Clone, Serialize)]
+pub struct UnresolvedInstance {
+    #[serde(serialize_with = "serialize_file_id")]
+    pub file_id: i64,
+    pub source_guid: Option<String>,
+    pub reason: String,
+}
+
+//----------------------------------------
+
+#[derive(Debug, Default)]
+pub struct Flattened {
+    pub documents: Vec<UnityDocument>,
+    pub unresolved: Vec<UnresolvedInstance>,
+}
+
+//----------------------------------------
+
+/// fileID Unity gives an object of a source prefab once instantiated by the
+/// PrefabInstance `instance`, also the fileID of its stripped placeholder
+pub fn instance_file_id(instance: i64, source: i64) -> i64 {
+    (instance ^ source) & i64::MAX
+}
+
+//----------------------------------------
+
+impl PrefabSources {
+    /// Uses the sidecar index when it is fresh, otherwise keeps every YAML
+    /// asset of a single pass until the pathnames say which are prefabs.
+    pub fn from_package(package: &Package) -> Result<PrefabSources> {
+        let mut buffers = Vec::<(String, Vec<u8>)>::new();
+        let mut pathnames = HashMap::<String, String>::new();
+
+        if let Some(index) = PackageIndex::load_sidecar(package)? {
+            let wanted = index
+                .guids
+                .iter()
+                .filter(|(_, entry)| entry.files.contains_key("asset"))
+                .filter(|(_, entry)| {
+                    entry
+                        .pathname
+                        .as_ref()
+                        .is_some_and(|pathname| pathname.ends_with(PREFAB_EXTENSION))
+                })
+                .map(|(guid, _)| (guid.clone(), "asset".to_owned()))
+                .collect::<Vec<(String, String)>>();
+
+            for ((guid, _), buffer) in wanted.iter().zip(index.read_many(package, &wanted)?) {
+                buffers.push((guid.clone(), buffer));
+            }
+        } else {
+            for file in package.clone().open()?.entries()? {
+                let mut file = file?;
+
+                let guid = file.guid()?;
+                match file.file_name()?.as_str() {
+                    "pathname" => {
+                        let mut s = String::new();
+                        file.read_to_string(&mut s)?;
+                        let pathname = s.split('\n').next().unwrap_or("").to_owned();
+                        pathnames.insert(guid, pathname);
+                    }
+                    "asset" => {
+                        let mut buffer = Vec::new();
+                        file.read_to_end(&mut buffer)?;
+                        if buffer.starts_with(b"%YAML") {
+                            buffers.push((guid, buffer));
+                        }
+                    }
+                    _ => (),
+                }
+            }
+
+            buffers.retain(|(guid, _)| {
+                pathnames
+                    .get(guid)
+                    .is_some_and(|pathname| pathname.ends_with(PREFAB_EXTENSION))
+            });
+        }
+
+        let prefabs = buffers
+            .into_iter()
+            .filter_map(|(guid, buffer)| {
+                let yaml = std::str::from_utf8(&buffer).ok()?;
+                Some((guid, unity_yaml::parse_recover(yaml).0))
+            })
+            .collect();
+
+        Ok(PrefabSources { prefabs })
+    }
+
+    /// Replace every PrefabInstance whose source prefab is available with
+    /// the source's objects, nested instances first, and apply its
+    /// `m_Modification`. Stripped placeholders are replaced by the objects
+    /// they stand for.
+    pub fn flatten(&self, documents: &[UnityDocument]) -> Flattened {
+        let mut resolver = Resolver {
+            sources: self,
+            resolved: HashMap::new(),
+            stack: Vec::new(),
+            unresolved: Vec::new(),
+        };
+
+        let documents = resolver.flatten(documents);
+        Flattened {
+            documents,
+            unresolved: resolver.unresolved,
+        }
+    }
+}
+
+//----------------------------------------
+
+struct Resolver<'a> {
+    sources: &'a PrefabSources,
+    /// guid -> flattened source prefab
+    resolved: HashMap<String, Option<Vec<UnityDocument>>>,
+    /// Sources being flattened, to catch prefabs nesting themselves
+    stack: Vec<String>,
+    unresolved: Vec<UnresolvedInstance>,
+}
+
+//----------------------------------------
+
+impl Resolver<'_> {
+    fn flatten(&mut self, documents: &[UnityDocument]) -> Vec<UnityDocument> {
+        let mut out = documents.to_vec();
+        let mut reparent = Vec::<(i64, i64)>::new();
+
+        let instances = documents
+            .iter()
+            .filter(|document| document.class_id == 1001 && !document.stripped)
+            .filter(|document| document.body.get("m_SourcePrefab").is_some());
+
+        for instance in instances {
+            let source_guid = instance
+                .body
+                .get("m_SourcePrefab")
+                .and_then(|source| source.get("guid"))
+                .and_then(Value::as_str)
+                .map(|guid| guid.to_owned());
+
+            let source = match source_guid.as_deref().map(|guid| self.source(guid)) {
+                Some(Ok(source)) => source,
+                Some(Err(reason)) => {
+                    self.unresolved.push(UnresolvedInstance {
+                        file_id: instance.file_id,
+                        source_guid,
+                        reason,
+                    });
+                    continue;
+                }
+                None => {
+                    self.unresolved.push(UnresolvedInstance {
+                        file_id: instance.file_id,
+                        source_guid,
+                        reason: "m_SourcePrefab has no guid".to_owned(),
+                    });
+                    continue;
+                }
+            };
+            let source_guid = source_guid.unwrap_or_default();
+
+            let mut instanced = instantiate(instance, &source_guid, &source);
+            let modification = instance.body.get("m_Modification");
+
+            if let Some(modification) = modification {
+                apply_modifications(instance.file_id, &source_guid, modification, &mut instanced);
+            }
+
+            // The source's root transforms go under m_TransformParent
+            let parent = modification
+                .and_then(|modification| modification.get("m_TransformParent"))
+                .and_then(reference_file_id)
+                .unwrap_or(0);
+            for document in instanced
+                .iter_mut()
+                .filter(|document| is_transform(document))
+            {
+                if link(document, "m_Father") == 0 && parent != 0 {
+                    set_reference(&mut document.body, "m_Father", parent);
+                    reparent.push((document.file_id, parent));
+                }
+            }
+
+            let ids = instanced
+                .iter()
+                .map(|document| document.file_id)
+                .collect::<HashSet<i64>>();
+            out.retain(|document| {
+                document.file_id != instance.file_id
+                    && !(document.stripped && ids.contains(&document.file_id))
+            });
+            out.extend(instanced);
+        }
+
+        attach_children(&mut out, &reparent);
+        attach_components(&mut out);
+        out
+    }
+
+    /// The flattened source prefab, or why it can't be used
+    fn source(&mut self, guid: &str) -> std::result::Result<Vec<UnityDocument>, String> {
+        if self.stack.iter().any(|g| g == guid) {
+            return Err(format!("prefab {} contains itself", guid));
+        }
+
+        if !self.resolved.contains_key(guid) {
+            let sources = self.sources;
+            let flattened = sources.prefabs.get(guid).map(|documents| {
+                self.stack.push(guid.to_owned());
+                let flattened = self.flatten(documents);
+                self.stack.pop();
+                flattened
+            });
+            self.resolved.insert(guid.to_owned(), flattened);
+        }
+
+        self.resolved[guid]
+            .clone()
+            .ok_or_else(|| format!("source prefab {} is not in the package", guid))
+    }
+}
+
+//----------------------------------------
+
+/// Copy the source's documents under their instanced fileIDs
+fn instantiate(
+    instance: &UnityDocument,
+    source_guid: &str,
+    source: &[UnityDocument],
+) -> Vec<UnityDocument> {
+    let source_ids = source
+        .iter()
+        .map(|document| document.file_id)
+        .collect::<HashSet<i64>>();
+
+    source
+        .iter()
+        .filter(|document| document.class_id != 1001)
+        .map(|document| {
+            let mut body = document.body.clone();
+            remap_local_references(&mut body, instance.file_id, &source_ids);
+
+            if let Value::Mapping(mapping) = &mut body {
+                mapping.insert(
+                    "m_CorrespondingSourceObject".into(),
+                    reference(document.file_id, Some(source_guid)),
+                );
+                mapping.insert("m_PrefabInstance".into(), reference(instance.file_id, None));
+            }
+
+            UnityDocument {
+                class_id: document.class_id,
+                file_id: instance_file_id(instance.file_id, document.file_id),
+                stripped: false,
+                type_name: document.type_name.clone(),
+                body,
+            }
+        })
+        .collect()
+}
+
+//----------------------------------------
+
+fn remap_local_references(value: &mut Value, instance: i64, source_ids: &HashSet<i64>) {
+    match value {
+        Value::Mapping(mapping) => {
+            let local = mapping
+                .get("fileID")
+                .and_then(Value::as_i64)
+                .filter(|file_id| source_ids.contains(file_id))
+                .filter(|_| mapping.get("guid").and_then(Value::as_str).is_none());
+
+            match local {
+                Some(file_id) => {
+                    mapping.insert("fileID".into(), instance_file_id(instance, file_id).into());
+                }
+                None => {
+                    for (_, value) in mapping.iter_mut() {
+                        remap_local_references(value, instance, source_ids);
+                    }
+                }
+            }
+        }
+        Value::Sequence(sequence) => {
+            for value in sequence {
+                remap_local_references(value, instance, source_ids);
+            }
+        }
+        _ => (),
+    }
+}
+
+//----------------------------------------
+
+fn apply_modifications(
+    instance: i64,
+    source_guid: &str,
+    modification: &Value,
+    instanced: &mut Vec<UnityDocument>,
+) {
+    // Only targets within this instance's source prefab can be applied
+    let target = |value: &Value| {
+        let guid = value.get("guid").and_then(Value::as_str);
+        match (reference_file_id(value), guid) {
+            (Some(file_id), Some(guid)) if guid == source_guid => {
+                Some(instance_file_id(instance, file_id))
+            }
+            _ => None,
+        }
+    };
+
+    let list = |key: &str| {
+        modification
+            .get(key)
+            .and_then(Value::as_sequence)
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    for change in list("m_Modifications") {
+        let (target, path) = match (
+            change.get("target").and_then(target),
+            change.get("propertyPath").and_then(Value::as_str),
+        ) {
+            (Some(target), Some(path)) => (target, path),
+            _ => continue,
+        };
+
+        let object_reference = change
+            .get("objectReference")
+            .filter(|reference| reference_file_id(reference).is_some_and(|id| id != 0));
+        let value = match object_reference {
+            Some(reference) => reference.clone(),
+            None => change.get("value").cloned().unwrap_or(Value::Null),
+        };
+
+        if let Some(document) = instanced.iter_mut().find(|d| d.file_id == target) {
+            set_property(&mut document.body, path, value);
+        }
+    }
+
+    for removed in list("m_RemovedComponents").iter().filter_map(target) {
+        remove_component(instanced, removed);
+    }
+
+    for removed in list("m_RemovedGameObjects").iter().filter_map(target) {
+        remove_game_object(instanced, removed);
+    }
+}
+
+//----------------------------------------
+
+/// Set a `propertyPath` like "m_LocalPosition.x", "m_Materials.Array.data[0]"
+/// or "m_Materials.Array.size"
+fn set_property(body: &mut Value, path: &str, value: Value) {
+    let segments = path.split('.').collect::<Vec<&str>>();
+    let mut current = body;
+    let mut i = 0;
+
+    while i < segments.len() {
+        let segment = segments[i];
+        let last = i + 1 == segments.len();
+
+        if segment == "Array" && i + 1 < segments.len() {
+            let next = segments[i + 1];
+            if !current.is_sequence() {
+                *current = Value::Sequence(Vec::new());
+            }
+            let sequence = current.as_sequence_mut().unwrap();
+
+            if next == "size" {
+                let size = value
+                    .as_u64()
+                    .or_else(|| value.as_str()?.parse().ok())
+                    .unwrap_or(0) as usize;
+                sequence.resize(size, Value::Null);
+                return;
+            }
+
+            let index = match next
+                .strip_prefix("data[")
+                .and_then(|rest| rest.strip_suffix(']'))
+                .and_then(|index| index.parse::<usize>().ok())
+            {
+                Some(index) => index,
+                None => return,
+            };
+            if sequence.len() <= index {
+                sequence.resize(index + 1, Value::Null);
+            }
+
+            if i + 2 == segments.len() {
+                sequence[index] = coerce(&sequence[index], value);
+                return;
+            }
+            current = &mut sequence[index];
+            i += 2;
+            continue;
+        }
+
+        if !current.is_mapping() {
+            *current = Value::Mapping(Mapping::new());
+        }
+        let mapping = current.as_mapping_mut().unwrap();
+
+        if last {
+            let existing = mapping.get(segment).cloned().unwrap_or(Value::Null);
+            mapping.insert(segment.into(), coerce(&existing, value));
+            return;
+        }
+
+        current = mapping.entry(segment.into()).or_insert(Value::Null);
+        i += 1;
+    }
+}
+
+//----------------------------------------
+
+/// Modification values are plain scalars, keep a string field a string
+fn coerce(existing: &Value, value: Value) -> Value {
+    match (existing, &value) {
+        (Value::String(_), Value::Number(n)) => Value::String(n.to_string()),
+        (Value::String(_), Value::Bool(b)) => Value::String(b.to_string()),
+        (Value::String(_), Value::Null) => Value::String(String::new()),
+        _ => value,
+    }
+}
+
+//----------------------------------------
+
+fn remove_component(documents: &mut Vec<UnityDocument>, component: i64) {
+    documents.retain(|document| document.file_id != component);
+
+    for document in documents.iter_mut() {
+        if let Some(Value::Sequence(list)) = document.body.get_mut("m_Component") {
+            list.retain(|item| component_file_id(item) != Some(component));
+        }
+    }
+}
+
+//----------------------------------------
+
+/// Remove a GameObject with its components and child GameObjects
+fn remove_game_object(documents: &mut Vec<UnityDocument>, game_object: i64) {
+    let components = documents
+        .iter()
+        .find(|document| document.file_id == game_object)
+        .and_then(|document| document.body.get("m_Component"))
+        .and_then(Value::as_sequence)
+        .map(|list| list.iter().filter_map(component_file_id).collect())
+        .unwrap_or_else(Vec::new);
+
+    let children = documents
+        .iter()
+        .filter(|document| components.contains(&document.file_id) && is_transform(document))
+        .flat_map(|transform| {
+            let children = transform
+                .body
+                .get("m_Children")
+                .and_then(Value::as_sequence)
+                .map(|children| children.iter().filter_map(reference_file_id).collect())
+                .unwrap_or_else(Vec::new);
+            children
+        })
+        .filter_map(|child| {
+            documents
+                .iter()
+                .find(|document| document.file_id == child)
+                .map(|transform| link(transform, "m_GameObject"))
+        })
+        .collect::<Vec<i64>>();
+
+    for child in children {
+        remove_game_object(documents, child);
+    }
+
+    documents.retain(|document| {
+        document.file_id != game_object && !components.contains(&document.file_id)
+    });
+
+    for document in documents
+        .iter_mut()
+        .filter(|document| is_transform(document))
+    {
+        if let Some(Value::Sequence(list)) = document.body.get_mut("m_Children") {
+            list.retain(|child| {
+                reference_file_id(child).is_some_and(|id| !components.contains(&id))
+            });
+        }
+    }
+}
+
+//----------------------------------------
+
+/// Add reparented instance roots to their new father's `m_Children`
+fn attach_children(documents: &mut [UnityDocument], reparent: &[(i64, i64)]) {
+    let fathers = documents
+        .iter()
+        .enumerate()
+        .map(|(i, document)| (document.file_id, i))
+        .collect::<BTreeMap<i64, usize>>();
+
+    for (child, father) in reparent {
+        if let Some(&i) = fathers.get(father) {
+            let children = sequence_entry(&mut documents[i].body, "m_Children");
+            if !children
+                .iter()
+                .any(|item| reference_file_id(item) == Some(*child))
+            {
+                children.push(reference(*child, None));
+            }
+        }
+    }
+}
+
+//----------------------------------------
+
+/// Components added to instanced GameObjects live in the outer asset and
+/// only point at the GameObject, list them in its `m_Component`
+fn attach_components(documents: &mut [UnityDocument]) {
+    let owners = documents
+        .iter()
+        .filter(|document| document.class_id != 1)
+        .filter_map(|document| {
+            let game_object = link(document, "m_GameObject");
+            (game_object != 0).then_some((game_object, document.file_id))
+        })
+        .collect::<Vec<(i64, i64)>>();
+
+    for (game_object, component) in owners {
+        let document = match documents
+            .iter_mut()
+            .find(|document| document.file_id == game_object && document.class_id == 1)
+        {
+            Some(document) => document,
+            None => continue,
+        };
+
+        let list = sequence_entry(&mut document.body, "m_Component");
+        if !list
+            .iter()
+            .any(|item| component_file_id(item) == Some(component))
+        {
+            let mut item = Mapping::new();
+            item.insert("component".into(), reference(component, None));
+            list.push(Value::Mapping(item));
+        }
+    }
+}
+
+//----------------------------------------
+
+fn sequence_entry<'a>(body: &'a mut Value, key: &str) -> &'a mut Vec<Value> {
+    if !body.is_mapping() {
+        *body = Value::Mapping(Mapping::new());
+    }
+    let entry = body
+        .as_mapping_mut()
+        .unwrap()
+        .entry(key.into())
+        .or_insert(Value::Null);
+    if !entry.is_sequence() {
+        *entry = Value::Sequence(Vec::new());
+    }
+    entry.as_sequence_mut().unwrap()
+}
+
+//----------------------------------------
+
+fn reference(file_id: i64, guid: Option<&str>) -> Value {
+    let mut mapping = Mapping::new();
+    mapping.insert("fileID".into(), file_id.into());
+    if let Some(guid) = guid {
+        mapping.insert("guid".into(), guid.into());
+        mapping.insert("type".into(), 3.into());
+    }
+    Value::Mapping(mapping)
+}
+
+//----------------------------------------
+
+fn set_reference(body: &mut Value, key: &str, file_id: i64) {
+    if let Value::Mapping(mapping) = body {
+        mapping.insert(key.into(), reference(file_id, None));
+    }
+}
+
+//----------------------------------------
+
+fn link(document: &UnityDocument, key: &str) -> i64 {
+    document
+        .body
+        .get(key)
+        .and_then(reference_file_id)
+        .unwrap_or(0)
+}
+
+//----------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE_GUID: &str = "55555555555555555555555555555555";
+    const NESTED_GUID: &str = "66666666666666666666666666666666";
+
+    /// Root "Source" with a MonoBehaviour and a "Child" GameObject
+    const SOURCE: &str = "\
+--- !u!1 &10
+GameObject:
+  m_Component:
+  - component: {fileID: 20}
+  - component: {fileID: 30}
+  m_Name: Source
+--- !u!4 &20
+Transform:
+  m_GameObject: {fileID: 10}
+  m_Children:
+  - {fileID: 50}
+  m_Father: {fileID: 0}
+--- !u!114 &30
+MonoBehaviour:
+  m_GameObject: {fileID: 10}
+  m_Enabled: 1
+--- !u!1 &40
+GameObject:
+  m_Component:
+  - component: {fileID: 50}
+  m_Name: Child
+--- !u!4 &50
+Transform:
+  m_GameObject: {fileID: 40}
+  m_Children: []
+  m_Father: {fileID: 20}
+";
+
+    /// "Outer" GameObject and an instance of `guid` under its transform
+    fn scene(guid: &str, modifications: &str) -> String {
+        format!(
+            "\
+--- !u!1 &1
+GameObject:
+  m_Component:
+  - component: {{fileID: 2}}
+  m_Name: Outer
+--- !u!4 &2
+Transform:
+  m_GameObject: {{fileID: 1}}
+  m_Children: []
+  m_Father: {{fileID: 0}}
+--- !u!1001 &100
+PrefabInstance:
+  m_Modification:
+    m_TransformParent: {{fileID: 2}}
+{modifications}
+  m_SourcePrefab: {{fileID: 100100000, guid: {guid}, type: 3}}
+"
+        )
+    }
+
+    fn sources(prefabs: &[(&str, &str)]) -> PrefabSources {
+        PrefabSources {
+            prefabs: prefabs
+                .iter()
+                .map(|(guid, yaml)| (guid.to_string(), unity_yaml::parse(yaml).unwrap()))
+                .collect(),
+        }
+    }
+
+    fn flatten(sources: &PrefabSources, yaml: &str) -> Vec<UnityDocument> {
+        let flattened = sources.flatten(&unity_yaml::parse(yaml).unwrap());
+        assert!(flattened.unresolved.is_empty());
+        flattened.documents
+    }
+
+    fn find(documents: &[UnityDocument], file_id: i64) -> Option<&UnityDocument> {
+        documents
+            .iter()
+            .find(|document| document.file_id == file_id)
+    }
+
+    fn links(document: &UnityDocument, key: &str) -> Vec<i64> {
+        document.body[key]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|item| reference_file_id(item.get("component").unwrap_or(item)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn instance_file_ids_xor_and_stay_positive() {
+        assert_eq!(instance_file_id(100, 10), 110);
+        assert_eq!(instance_file_id(100, 100), 0);
+        assert_eq!(instance_file_id(-1, 5), i64::MAX - 5);
+        assert!(instance_file_id(i64::MIN, 1) >= 0);
+    }
+
+    #[test]
+    fn property_paths_index_and_resize_arrays() {
+        let mut body = serde_yaml::from_str::<Value>(
+            "m_Materials:\n- {fileID: 1}\nm_LocalPosition: {x: 0, y: 0, z: 0}\nm_Name: A\n",
+        )
+        .unwrap();
+
+        set_property(&mut body, "m_Materials.Array.size", "3".into());
+        assert_eq!(body["m_Materials"].as_sequence().unwrap().len(), 3);
+
+        set_property(&mut body, "m_Materials.Array.data[2]", reference(7, None));
+        assert_eq!(reference_file_id(&body["m_Materials"][2]), Some(7));
+        assert_eq!(reference_file_id(&body["m_Materials"][0]), Some(1));
+
+        set_property(&mut body, "m_Materials.Array.data[4].fileID", 9.into());
+        assert_eq!(body["m_Materials"].as_sequence().unwrap().len(), 5);
+        assert_eq!(reference_file_id(&body["m_Materials"][4]), Some(9));
+
+        set_property(&mut body, "m_Materials.Array.size", 1.into());
+        assert_eq!(body["m_Materials"].as_sequence().unwrap().len(), 1);
+
+        set_property(&mut body, "m_LocalPosition.x", 1.5.into());
+        assert_eq!(body["m_LocalPosition"]["x"].as_f64(), Some(1.5));
+
+        set_property(&mut body, "m_Name", 2.into());
+        assert_eq!(body["m_Name"].as_str(), Some("2"));
+    }
+
+    #[test]
+    fn instances_are_reparented_and_modified() {
+        let modifications = format!(
+            "    m_Modifications:
+    - target: {{fileID: 10, guid: {SOURCE_GUID}, type: 3}}
+      propertyPath: m_Name
+      value: Renamed
+      objectReference: {{fileID: 0}}
+    - target: {{fileID: 10, guid: 00000000000000000000000000000000, type: 3}}
+      propertyPath: m_Name
+      value: Elsewhere
+      objectReference: {{fileID: 0}}"
+        );
+        let documents = flatten(
+            &sources(&[(SOURCE_GUID, SOURCE)]),
+            &scene(SOURCE_GUID, &modifications),
+        );
+
+        assert!(find(&documents, 100).is_none());
+        assert_eq!(documents.len(), 2 + 5);
+
+        let root = find(&documents, instance_file_id(100, 10)).unwrap();
+        assert_eq!(root.body["m_Name"].as_str(), Some("Renamed"));
+        assert_eq!(
+            links(root, "m_Component"),
+            [instance_file_id(100, 20), instance_file_id(100, 30)]
+        );
+
+        let transform = find(&documents, instance_file_id(100, 20)).unwrap();
+        assert_eq!(link(transform, "m_Father"), 2);
+        assert_eq!(links(transform, "m_Children"), [instance_file_id(100, 50)]);
+        assert_eq!(
+            transform.body["m_CorrespondingSourceObject"]["guid"].as_str(),
+            Some(SOURCE_GUID)
+        );
+        assert_eq!(link(transform, "m_PrefabInstance"), 100);
+
+        let outer = find(&documents, 2).unwrap();
+        assert_eq!(links(outer, "m_Children"), [instance_file_id(100, 20)]);
+    }
+
+    #[test]
+    fn removed_components_and_game_objects_are_dropped() {
+        let modifications = format!(
+            "    m_Modifications: []
+    m_RemovedComponents:
+    - {{fileID: 30, guid: {SOURCE_GUID}, type: 3}}
+    m_RemovedGameObjects:
+    - {{fileID: 40, guid: {SOURCE_GUID}, type: 3}}"
+        );
+        let documents = flatten(
+            &sources(&[(SOURCE_GUID, SOURCE)]),
+            &scene(SOURCE_GUID, &modifications),
+        );
+
+        for removed in [30, 40, 50] {
+            assert!(find(&documents, instance_file_id(100, removed)).is_none());
+        }
+
+        let root = find(&documents, instance_file_id(100, 10)).unwrap();
+        assert_eq!(links(root, "m_Component"), [instance_file_id(100, 20)]);
+
+        let transform = find(&documents, instance_file_id(100, 20)).unwrap();
+        assert!(links(transform, "m_Children").is_empty());
+    }
+
+    #[test]
+    fn nested_instances_are_flattened_first() {
+        // A prefab made of an instance of SOURCE, renaming its root
+        let nested = format!(
+            "\
+--- !u!1001 &200
+PrefabInstance:
+  m_Modification:
+    m_TransformParent: {{fileID: 0}}
+    m_Modifications:
+    - target: {{fileID: 10, guid: {SOURCE_GUID}, type: 3}}
+      propertyPath: m_Name
+      value: Nested
+      objectReference: {{fileID: 0}}
+  m_SourcePrefab: {{fileID: 100100000, guid: {SOURCE_GUID}, type: 3}}
+"
+        );
+        let sources = sources(&[(SOURCE_GUID, SOURCE), (NESTED_GUID, &nested)]);
+        let documents = flatten(&sources, &scene(NESTED_GUID, "    m_Modifications: []"));
+
+        assert!(documents.iter().all(|document| document.class_id != 1001));
+        assert_eq!(documents.len(), 2 + 5);
+
+        let root = instance_file_id(100, instance_file_id(200, 10));
+        let root = find(&documents, root).unwrap();
+        assert_eq!(root.body["m_Name"].as_str(), Some("Nested"));
+
+        let transform = instance_file_id(100, instance_file_id(200, 20));
+        assert_eq!(link(find(&documents, transform).unwrap(), "m_Father"), 2);
+        assert_eq!(
+            links(find(&documents, 2).unwrap(), "m_Children"),
+            [transform]
+        );
+    }
+
+    #[test]
+    fn missing_and_self_nesting_sources_are_unresolved() {
+        let yaml = scene(NESTED_GUID, "    m_Modifications: []");
+        let flattened = sources(&[]).flatten(&unity_yaml::parse(&yaml).unwrap());
+        assert_eq!(flattened.unresolved.len(), 1);
+        assert!(find(&flattened.documents, 100).is_some());
+
+        let flattened =
+            sources(&[(NESTED_GUID, &yaml)]).flatten(&unity_yaml::parse(&yaml).unwrap());
+        assert!(flattened
+            .unresolved
+            .iter()
+            .any(|unresolved| unresolved.reason.contains("contains itself")));
+    }
+}
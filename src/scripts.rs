@@ -0,0 +1,377 @@
+//----------------------------------------
+
+use std::collections::HashMap;
+
+use serde_yaml::Value;
+
+use crate::error::Result;
+use crate::index::PackageIndex;
+use crate::package::Package;
+
+//----------------------------------------
+
+const SCRIPT_EXTENSION: &str = ".cs";
+
+//----------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct ScriptInfo {
+    pub pathname: String,
+    /// Namespace qualified class name, None if it could not be found
+    pub class: Option<String>,
+}
+
+//----------------------------------------
+
+/// guid -> `.cs` script in the package, for resolving `m_Script` references
+#[derive(Debug, Default)]
+pub struct ScriptIndex {
+    pub scripts: HashMap<String, ScriptInfo>,
+}
+
+//----------------------------------------
+
+impl ScriptIndex {
+    /// Uses the sidecar index when it is fresh, otherwise reads the package
+    /// twice, once for pathnames and once for the scripts' source.
+    pub fn build(package: &Package) -> Result<ScriptIndex> {
+        let pathnames = match PackageIndex::load_sidecar(package)? {
+            Some(index) => return ScriptIndex::from_index(package, &index),
+            None => script_pathnames(package)?,
+        };
+
+        let mut scripts = HashMap::new();
+
+        for file in package.clone().open()?.entries()? {
+            let mut file = file?;
+
+            let guid = file.guid()?;
+            let pathname = match pathnames.get(&guid) {
+                Some(pathname) if file.file_name()? == "asset" => pathname,
+                _ => continue,
+            };
+
+            let mut buffer = Vec::new();
+            file.read_to_end(&mut buffer)?;
+            scripts.insert(guid, script_info(pathname, &buffer));
+        }
+
+        Ok(ScriptIndex { scripts })
+    }
+
+    fn from_index(package: &Package, index: &PackageIndex) -> Result<ScriptIndex> {
+        let pathnames = index
+            .guids
+            .iter()
+            .filter(|(_, entry)| entry.files.contains_key("asset"))
+            .filter_map(|(guid, entry)| Some((guid.clone(), entry.pathname.clone()?)))
+            .filter(|(_, pathname)| pathname.ends_with(SCRIPT_EXTENSION))
+            .collect::<Vec<(String, String)>>();
+
+        let wanted = pathnames
+            .iter()
+            .map(|(guid, _)| (guid.clone(), "asset".to_owned()))
+            .collect::<Vec<(String, String)>>();
+        let sources = index.read_many(package, &wanted)?;
+
+        Ok(ScriptIndex {
+            scripts: pathnames
+                .into_iter()
+                .zip(sources)
+                .map(|((guid, pathname), source)| {
+                    let info = script_info(&pathname, &source);
+                    (guid, info)
+                })
+                .collect(),
+        })
+    }
+
+    pub fn get(&self, guid: &str) -> Option<&ScriptInfo> {
+        self.scripts.get(guid)
+    }
+
+    /// Add `_script_path` and `_script_class` to each MonoBehaviour of a
+    /// `documents_to_value` list whose `m_Script` is in the package
+    pub fn annotate(&self, documents: &mut Value) {
+        let documents = match documents.as_sequence_mut() {
+            Some(documents) => documents,
+            None => return,
+        };
+
+        for document in documents.iter_mut().filter_map(Value::as_mapping_mut) {
+            if document.get("type").and_then(Value::as_str) != Some("MonoBehaviour") {
+                continue;
+            }
+
+            let script = document
+                .get("content")
+                .and_then(|content| content.get("m_Script"))
+                .and_then(|script| script.get("guid"))
+                .and_then(Value::as_str)
+                .and_then(|guid| self.get(guid))
+                .cloned();
+
+            if let Some(script) = script {
+                document.insert("_script_path".into(), script.pathname.into());
+                document.insert(
+                    "_script_class".into(),
+                    script.class.map(Value::String).unwrap_or(Value::Null),
+                );
+            }
+        }
+    }
+}
+
+//----------------------------------------
+
+fn script_pathnames(package: &Package) -> Result<HashMap<String, String>> {
+    let mut pathnames = HashMap::new();
+
+    for file in package.clone().open()?.entries()? {
+        let mut file = file?;
+
+        if file.file_name()? != "pathname" {
+            continue;
+        }
+
+        let mut s = String::new();
+        file.read_to_string(&mut s)?;
+
+        let pathname = s.split('\n').next().unwrap_or("");
+        if pathname.ends_with(SCRIPT_EXTENSION) {
+            pathnames.insert(file.guid()?, pathname.to_owned());
+        }
+    }
+
+    Ok(pathnames)
+}
+
+//----------------------------------------
+
+fn script_info(pathname: &str, source: &[u8]) -> ScriptInfo {
+    let stem = pathname
+        .rsplit('/')
+        .next()
+        .unwrap_or(pathname)
+        .trim_end_matches(SCRIPT_EXTENSION);
+
+    ScriptInfo {
+        pathname: pathname.to_owned(),
+        class: script_class(&String::from_utf8_lossy(source), stem),
+    }
+}
+
+//----------------------------------------
+
+/// Find the class Unity binds the script to. Unity wants it named after the
+/// file, so that one wins, otherwise the first class declared.
+pub fn script_class(source: &str, file_stem: &str) -> Option<String> {
+    let source = strip_comments_and_strings(source);
+    let tokens = source
+        .split(|c: char| !(c.is_alphanumeric() || "_.{};".contains(c)))
+        .flat_map(split_braces)
+        .filter(|token| !token.is_empty())
+        .collect::<Vec<&str>>();
+
+    // Namespaces open at each depth, file scoped ones at depth 0
+    let mut namespaces = Vec::<(usize, String)>::new();
+    let mut depth = 0usize;
+    let mut pending_namespace = None;
+    let mut classes = Vec::new();
+
+    let mut i = 0;
+    while i < tokens.len() {
+        match tokens[i] {
+            "{" => {
+                depth += 1;
+                if let Some(namespace) = pending_namespace.take() {
+                    namespaces.push((depth, namespace));
+                }
+            }
+            "}" => {
+                namespaces.retain(|(d, _)| *d < depth);
+                depth = depth.saturating_sub(1);
+            }
+            ";" => {
+                // namespace Foo;
+                if let Some(namespace) = pending_namespace.take() {
+                    namespaces.push((0, namespace));
+                }
+            }
+            "namespace" if i + 1 < tokens.len() => {
+                pending_namespace = Some(tokens[i + 1].to_owned());
+                i += 1;
+            }
+            // Not "where T : class"
+            "class" if i + 1 < tokens.len() && is_identifier(tokens[i + 1]) => {
+                let name = tokens[i + 1];
+                let qualified = namespaces
+                    .iter()
+                    .map(|(_, namespace)| namespace.as_str())
+                    .chain(std::iter::once(name))
+                    .collect::<Vec<&str>>()
+                    .join(".");
+                classes.push((name.to_owned(), qualified));
+                i += 1;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+
+    classes
+        .iter()
+        .find(|(name, _)| name == file_stem)
+        .or_else(|| classes.first())
+        .map(|(_, qualified)| qualified.clone())
+}
+
+//----------------------------------------
+
+fn is_identifier(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_alphabetic() || c == '_') && !token.contains('.')
+}
+
+//----------------------------------------
+
+/// "Foo{" -> "Foo", "{", keeping braces and semicolons as their own tokens
+fn split_braces(token: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+
+    for (i, c) in token.char_indices() {
+        if matches!(c, '{' | '}' | ';') {
+            parts.push(&token[start..i]);
+            parts.push(&token[i..i + 1]);
+            start = i + 1;
+        }
+    }
+
+    parts.push(&token[start..]);
+    parts
+}
+
+//----------------------------------------
+
+fn strip_comments_and_strings(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('/', Some('/')) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                out.push('\n');
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+                out.push(' ');
+            }
+            // Verbatim @"..", $@".." and @$"..", where "" is the only escape
+            ('@', Some('"')) | ('@', Some('$')) | ('$', Some('@')) => {
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                }
+                while let Some(c) = chars.next() {
+                    if c == '"' && chars.next_if_eq(&'"').is_none() {
+                        break;
+                    }
+                }
+                out.push(' ');
+            }
+            ('"', _) | ('\'', _) => {
+                let mut escaped = false;
+                for next in chars.by_ref() {
+                    if !escaped && next == c {
+                        break;
+                    }
+                    escaped = !escaped && next == '\\';
+                }
+                out.push(' ');
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+//----------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_namespaces_qualify_the_class() {
+        let source = "using UnityEngine;\n\
+                      namespace Game.Player {\n\
+                      \x20   public class Mover : MonoBehaviour { }\n\
+                      }\n\
+                      public class Outside { }\n";
+
+        assert_eq!(
+            script_class(source, "Mover").as_deref(),
+            Some("Game.Player.Mover")
+        );
+        assert_eq!(script_class(source, "Outside").as_deref(), Some("Outside"));
+    }
+
+    #[test]
+    fn file_scoped_namespaces_qualify_every_class() {
+        let source = "namespace Game;\n\
+                      public class Helper { }\n\
+                      public class Mover : MonoBehaviour { }\n";
+
+        assert_eq!(script_class(source, "Mover").as_deref(), Some("Game.Mover"));
+    }
+
+    #[test]
+    fn classes_in_comments_and_strings_are_ignored() {
+        let source = "// class Commented { }\n\
+                      /* namespace Wrong { class Blocked { } } */\n\
+                      public class Mover : MonoBehaviour {\n\
+                      \x20   string s = \"class Quoted { \\\" }\";\n\
+                      \x20   char c = '{';\n\
+                      }\n";
+
+        assert_eq!(script_class(source, "Other").as_deref(), Some("Mover"));
+    }
+
+    #[test]
+    fn the_class_named_after_the_file_wins() {
+        let source = "class Helper<T> where T : class { }\n\
+                      public class Mover : MonoBehaviour { }\n";
+
+        assert_eq!(script_class(source, "Mover").as_deref(), Some("Mover"));
+        assert_eq!(script_class(source, "Missing").as_deref(), Some("Helper"));
+        assert_eq!(script_class("using System;", "Mover"), None);
+    }
+
+    #[test]
+    fn verbatim_strings_end_at_a_lone_quote() {
+        let source = r#"
+class Settings { string dir = @"C:\Temp\"; }
+class Mover : MonoBehaviour {
+    string quote = @"""class Quoted { }""";
+    string both = $@"{dir}\" + @$"{dir}\";
+}
+"#;
+
+        assert_eq!(script_class(source, "Mover").as_deref(), Some("Mover"));
+        assert_eq!(script_class(source, "Quoted").as_deref(), Some("Settings"));
+    }
+}
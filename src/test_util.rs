@@ -0,0 +1,79 @@
+//----------------------------------------
+
+//! Packages built in memory for the unit tests
+
+use crate::package::Package;
+
+//----------------------------------------
+
+/// One tar entry, a regular file with mtime 0 unless changed
+pub struct Entry {
+    pub name: String,
+    pub data: Vec<u8>,
+    pub entry_type: tar::EntryType,
+    pub mtime: u64,
+}
+
+//----------------------------------------
+
+pub fn entry(name: impl Into<String>, data: impl AsRef<[u8]>) -> Entry {
+    Entry {
+        name: name.into(),
+        data: data.as_ref().to_vec(),
+        entry_type: tar::EntryType::Regular,
+        mtime: 0,
+    }
+}
+
+//----------------------------------------
+
+impl Entry {
+    pub fn mtime(mut self, mtime: u64) -> Entry {
+        self.mtime = mtime;
+        self
+    }
+
+    pub fn entry_type(mut self, entry_type: tar::EntryType) -> Entry {
+        self.entry_type = entry_type;
+        self
+    }
+}
+
+//----------------------------------------
+
+/// An uncompressed tar, names are written as is so they can be unsafe
+pub fn tar(entries: &[Entry]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.as_gnu_mut().unwrap().name[..entry.name.len()]
+            .copy_from_slice(entry.name.as_bytes());
+        header.set_entry_type(entry.entry_type);
+        header.set_mode(0o644);
+        header.set_mtime(entry.mtime);
+        header.set_size(entry.data.len() as u64);
+        header.set_cksum();
+        builder.append(&header, entry.data.as_slice()).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+//----------------------------------------
+
+pub fn package(data: &[u8]) -> Package {
+    Package::from_bytes(data).unwrap()
+}
+
+//----------------------------------------
+
+pub fn guid(n: u8) -> String {
+    format!("{:032x}", n)
+}
+
+//----------------------------------------
+
+pub fn meta(guid: &str) -> String {
+    format!("fileFormatVersion: 2\nguid: {}\n", guid)
+}
+
+//----------------------------------------
@@ -0,0 +1,853 @@
+//----------------------------------------
+
+// https://docs.unity3d.com/Manual/class-TextureImporter.html
+// https://www.adobe.com/devnet-apps/photoshop/fileformatashtml/
+// https://registry.khronos.org/KTX/specs/2.0/ktxspec.v2.html
+
+//----------------------------------------
+
+use std::io::Cursor;
+use std::path::Path;
+use std::str::FromStr;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageBuffer, ImageFormat, Rgb, RgbImage, Rgba, Rgba32FImage, RgbaImage};
+use serde_yaml::Value;
+
+use crate::error::{Error, Result};
+
+//----------------------------------------
+
+/// Source formats Unity imports as textures
+const TEXTURE_EXTENSIONS: &[&str] = &[
+    "psd", "tga", "tif", "tiff", "exr", "hdr", "png", "jpg", "jpeg", "bmp",
+];
+
+/// Unity's default "Max Size"
+const DEFAULT_MAX_SIZE: u32 = 2048;
+
+/// Unity's largest texture, 16384x16384, bounds what a PSD header may ask for
+const MAX_PSD_PIXELS: usize = 16384 * 16384;
+
+const KTX2_IDENTIFIER: &[u8; 12] = b"\xabKTX 20\xbb\r\n\x1a\n";
+const VK_FORMAT_R8G8B8A8_UNORM: u32 = 37;
+const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+
+//----------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFormat {
+    Png,
+    Webp,
+    Ktx2,
+}
+
+//----------------------------------------
+
+impl TextureFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TextureFormat::Png => "png",
+            TextureFormat::Webp => "webp",
+            TextureFormat::Ktx2 => "ktx2",
+        }
+    }
+}
+
+//----------------------------------------
+
+impl FromStr for TextureFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(TextureFormat::Png),
+            "webp" => Ok(TextureFormat::Webp),
+            "ktx2" => Ok(TextureFormat::Ktx2),
+            _ => Err(format!("{} is not one of png, webp, ktx2", s)),
+        }
+    }
+}
+
+//----------------------------------------
+
+/// The TextureImporter settings that change the converted pixels
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImporterSettings {
+    /// Color texture, false for linear data
+    pub srgb: bool,
+    pub max_size: u32,
+    /// Bleed color into fully transparent pixels
+    pub alpha_is_transparency: bool,
+    /// Texture Type "Normal map", always linear
+    pub normal_map: bool,
+    pub mipmaps: bool,
+}
+
+//----------------------------------------
+
+impl Default for ImporterSettings {
+    fn default() -> Self {
+        ImporterSettings {
+            srgb: true,
+            max_size: DEFAULT_MAX_SIZE,
+            alpha_is_transparency: false,
+            normal_map: false,
+            mipmaps: true,
+        }
+    }
+}
+
+//----------------------------------------
+
+impl ImporterSettings {
+    /// Read from a parsed `asset.meta`, either plain or in the
+    /// `{type, content}` shape of `asset_meta_yaml_cleanup`. Defaults for
+    /// anything missing, including metas without a TextureImporter.
+    pub fn from_meta(meta: &Value) -> ImporterSettings {
+        let meta = meta
+            .as_sequence()
+            .and_then(|documents| documents.first())
+            .unwrap_or(meta);
+        let importer = match meta.get("type").and_then(Value::as_str) {
+            Some("TextureImporter") => meta.get("content"),
+            _ => meta.get("TextureImporter"),
+        };
+        let importer = match importer {
+            Some(importer) => importer,
+            None => return ImporterSettings::default(),
+        };
+
+        let flag = |value: Option<&Value>| value.and_then(Value::as_i64).map(|v| v != 0);
+        let mipmaps = importer.get("mipmaps");
+        let defaults = ImporterSettings::default();
+
+        // Before 5.5 the sRGB toggle was "linearTexture", inverted
+        let srgb = flag(mipmaps.and_then(|m| m.get("sRGBTexture")))
+            .or_else(|| flag(mipmaps.and_then(|m| m.get("linearTexture"))).map(|linear| !linear))
+            .unwrap_or(defaults.srgb);
+
+        let default_platform = importer
+            .get("platformSettings")
+            .and_then(Value::as_sequence)
+            .and_then(|platforms| {
+                platforms.iter().find(|platform| {
+                    platform.get("buildTarget").and_then(Value::as_str)
+                        == Some("DefaultTexturePlatform")
+                })
+            });
+        let max_size = default_platform
+            .and_then(|platform| platform.get("maxTextureSize"))
+            .or_else(|| importer.get("maxTextureSize"))
+            .and_then(Value::as_u64)
+            .map(|size| size as u32)
+            .filter(|&size| size > 0)
+            .unwrap_or(defaults.max_size);
+
+        ImporterSettings {
+            srgb,
+            max_size,
+            alpha_is_transparency: flag(importer.get("alphaIsTransparency"))
+                .unwrap_or(defaults.alpha_is_transparency),
+            normal_map: importer.get("textureType").and_then(Value::as_i64) == Some(1)
+                || flag(importer.get("normalMap")).unwrap_or(false),
+            mipmaps: flag(mipmaps.and_then(|m| m.get("enableMipMap"))).unwrap_or(defaults.mipmaps),
+        }
+    }
+
+    /// Normal maps are stored linear whatever the sRGB toggle says
+    pub fn is_srgb(&self) -> bool {
+        self.srgb && !self.normal_map
+    }
+}
+
+//----------------------------------------
+
+/// The pathname has a source format Unity imports as a texture
+pub fn is_texture(pathname: &str) -> bool {
+    extension(pathname).is_some_and(|extension| TEXTURE_EXTENSIONS.contains(&extension.as_str()))
+}
+
+//----------------------------------------
+
+/// Decode a texture source, apply the importer settings, and encode it
+pub fn convert(
+    data: &[u8],
+    pathname: &str,
+    settings: &ImporterSettings,
+    format: TextureFormat,
+) -> Result<Vec<u8>> {
+    let image = decode(data, pathname)?;
+    let image = to_display(image, settings);
+    let image = fit(image, settings.max_size);
+
+    let image = if settings.alpha_is_transparency {
+        let mut image = image.to_rgba8();
+        bleed_alpha(&mut image);
+        DynamicImage::ImageRgba8(image)
+    } else {
+        image
+    };
+
+    match format {
+        TextureFormat::Png => encode(&image, ImageFormat::Png, pathname),
+        TextureFormat::Webp => encode(&image, ImageFormat::WebP, pathname),
+        TextureFormat::Ktx2 => Ok(ktx2(&image.to_rgba8(), settings)),
+    }
+}
+
+//----------------------------------------
+
+fn extension(pathname: &str) -> Option<String> {
+    Path::new(pathname)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+}
+
+//----------------------------------------
+
+fn decode(data: &[u8], pathname: &str) -> Result<DynamicImage> {
+    let bad = |err: String| Error::BadTexture(format!("{}: {}", pathname, err));
+
+    if extension(pathname).as_deref() == Some("psd") {
+        return psd(data).map_err(bad);
+    }
+
+    // TGA has no magic, trust the extension before sniffing
+    let format = ImageFormat::from_path(pathname)
+        .ok()
+        .or_else(|| image::guess_format(data).ok())
+        .ok_or_else(|| bad("unknown image format".to_owned()))?;
+
+    image::load_from_memory_with_format(data, format).map_err(|err| bad(err.to_string()))
+}
+
+//----------------------------------------
+
+/// 8 bit, and for float sources (EXR, HDR) encoded with the sRGB curve
+/// unless the importer marks the texture linear
+fn to_display(image: DynamicImage, settings: &ImporterSettings) -> DynamicImage {
+    match image {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) if settings.is_srgb() => {
+            let mut image = image.to_rgba32f();
+            for pixel in image.pixels_mut() {
+                for channel in &mut pixel.0[..3] {
+                    *channel = linear_to_srgb(*channel);
+                }
+            }
+            DynamicImage::ImageRgba32F(image).into_rgba8().into()
+        }
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => image.into_rgba8().into(),
+        DynamicImage::ImageLuma16(_) | DynamicImage::ImageLumaA16(_) => {
+            image.into_luma_alpha8().into()
+        }
+        DynamicImage::ImageRgb16(_) => image.into_rgb8().into(),
+        DynamicImage::ImageRgba16(_) => image.into_rgba8().into(),
+        image => image,
+    }
+}
+
+//----------------------------------------
+
+fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+//----------------------------------------
+
+/// Scale down so the longer side fits Max Size, keeping the aspect ratio
+fn fit(image: DynamicImage, max_size: u32) -> DynamicImage {
+    if image.width() <= max_size && image.height() <= max_size {
+        return image;
+    }
+    image.resize(max_size, max_size, FilterType::Lanczos3)
+}
+
+//----------------------------------------
+
+/// Fill the color of fully transparent pixels from opaque neighbours, so
+/// filtering does not pull in black or matte fringes
+fn bleed_alpha(image: &mut RgbaImage) {
+    let (width, height) = image.dimensions();
+    let index = |x: u32, y: u32| (y * width + x) as usize;
+
+    let mut filled = image.pixels().map(|p| p[3] != 0).collect::<Vec<bool>>();
+    if filled.iter().all(|&f| f) || !filled.iter().any(|&f| f) {
+        return;
+    }
+
+    // Breadth first from the opaque pixels, a ring at a time so each pixel
+    // averages the rings before it and is visited once
+    let mut queued = filled.clone();
+    let mut ring = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|&(x, y)| filled[index(x, y)])
+        .collect::<Vec<(u32, u32)>>();
+
+    while !ring.is_empty() {
+        let mut next = Vec::new();
+        for &(x, y) in &ring {
+            for (nx, ny) in neighbours(x, y, width, height) {
+                if !queued[index(nx, ny)] {
+                    queued[index(nx, ny)] = true;
+                    next.push((nx, ny));
+                }
+            }
+        }
+
+        let colors = next
+            .iter()
+            .map(|&(x, y)| {
+                let mut sum = [0u32; 3];
+                let mut count = 0;
+                for (nx, ny) in neighbours(x, y, width, height) {
+                    if filled[index(nx, ny)] {
+                        let p = image.get_pixel(nx, ny);
+                        for (sum, c) in sum.iter_mut().zip(&p.0[..3]) {
+                            *sum += *c as u32;
+                        }
+                        count += 1;
+                    }
+                }
+                sum.map(|s| (s / count) as u8)
+            })
+            .collect::<Vec<[u8; 3]>>();
+
+        for (&(x, y), color) in next.iter().zip(colors) {
+            image.get_pixel_mut(x, y).0[..3].copy_from_slice(&color);
+            filled[index(x, y)] = true;
+        }
+        ring = next;
+    }
+}
+
+//----------------------------------------
+
+/// The up to 8 pixels around (x, y)
+fn neighbours(x: u32, y: u32, width: u32, height: u32) -> impl Iterator<Item = (u32, u32)> {
+    [
+        (-1, 0),
+        (1, 0),
+        (0, -1),
+        (0, 1),
+        (-1, -1),
+        (1, -1),
+        (-1, 1),
+        (1, 1),
+    ]
+    .into_iter()
+    .filter_map(move |(dx, dy)| {
+        let nx = x.checked_add_signed(dx).filter(|&nx| nx < width)?;
+        let ny = y.checked_add_signed(dy).filter(|&ny| ny < height)?;
+        Some((nx, ny))
+    })
+}
+
+//----------------------------------------
+
+fn encode(image: &DynamicImage, format: ImageFormat, pathname: &str) -> Result<Vec<u8>> {
+    // The WebP encoder takes 8 bit RGB(A) only
+    let image = match (format, image.color().has_alpha()) {
+        (ImageFormat::WebP, true) => DynamicImage::ImageRgba8(image.to_rgba8()),
+        (ImageFormat::WebP, false) => DynamicImage::ImageRgb8(image.to_rgb8()),
+        _ => image.clone(),
+    };
+
+    let mut out = Cursor::new(Vec::new());
+    image
+        .write_to(&mut out, format)
+        .map_err(|err| Error::BadTexture(format!("{}: {}", pathname, err)))?;
+    Ok(out.into_inner())
+}
+
+//----------------------------------------
+
+/// Uncompressed RGBA8 KTX2, with a box filtered mip chain when the
+/// importer generates mipmaps
+fn ktx2(image: &RgbaImage, settings: &ImporterSettings) -> Vec<u8> {
+    let mut levels = vec![image.clone()];
+    if settings.mipmaps {
+        while let Some(last) = levels.last().filter(|l| l.width() > 1 || l.height() > 1) {
+            let (width, height) = ((last.width() / 2).max(1), (last.height() / 2).max(1));
+            levels.push(image::imageops::resize(
+                last,
+                width,
+                height,
+                FilterType::Triangle,
+            ));
+        }
+    }
+
+    let srgb = settings.is_srgb();
+    let dfd = data_format_descriptor(srgb);
+    let kvd = key_value(b"KTXwriter", env!("CARGO_PKG_NAME").as_bytes());
+
+    let header_size = 12 + 9 * 4 + 4 * 4 + 2 * 8;
+    let level_index_size = levels.len() * 3 * 8;
+    let dfd_offset = header_size + level_index_size;
+    let kvd_offset = dfd_offset + dfd.len();
+    let mut data_offset = kvd_offset + kvd.len();
+
+    // Levels are stored smallest first, each 4 byte aligned
+    let mut offsets = vec![0usize; levels.len()];
+    for (i, level) in levels.iter().enumerate().rev() {
+        data_offset = data_offset.next_multiple_of(4);
+        offsets[i] = data_offset;
+        data_offset += level.as_raw().len();
+    }
+
+    let mut out = Vec::with_capacity(data_offset);
+    out.extend_from_slice(KTX2_IDENTIFIER);
+    let format = if srgb {
+        VK_FORMAT_R8G8B8A8_SRGB
+    } else {
+        VK_FORMAT_R8G8B8A8_UNORM
+    };
+    for value in [
+        format,
+        1, // typeSize
+        image.width(),
+        image.height(),
+        0, // pixelDepth
+        0, // layerCount
+        1, // faceCount
+        levels.len() as u32,
+        0, // supercompressionScheme
+    ] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [dfd_offset, dfd.len(), kvd_offset, kvd.len()] {
+        out.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+    out.extend_from_slice(&[0; 16]); // No supercompression global data
+
+    for (level, offset) in levels.iter().zip(&offsets) {
+        let length = level.as_raw().len() as u64;
+        out.extend_from_slice(&(*offset as u64).to_le_bytes());
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(&length.to_le_bytes());
+    }
+
+    out.extend_from_slice(&dfd);
+    out.extend_from_slice(&kvd);
+
+    for (level, offset) in levels.iter().zip(&offsets).rev() {
+        out.resize(*offset, 0);
+        out.extend_from_slice(level.as_raw());
+    }
+
+    out
+}
+
+//----------------------------------------
+
+/// Basic descriptor block for 8 bit RGBA
+fn data_format_descriptor(srgb: bool) -> Vec<u8> {
+    const SAMPLES: usize = 4;
+    let block_size = 24 + 16 * SAMPLES;
+
+    let mut dfd = Vec::with_capacity(4 + block_size);
+    dfd.extend_from_slice(&((4 + block_size) as u32).to_le_bytes());
+    dfd.extend_from_slice(&0u32.to_le_bytes()); // Khronos vendor, basic type
+    dfd.extend_from_slice(&2u16.to_le_bytes()); // version
+    dfd.extend_from_slice(&(block_size as u16).to_le_bytes());
+    dfd.extend_from_slice(&[
+        1,                        // KHR_DF_MODEL_RGBSDA
+        1,                        // KHR_DF_PRIMARIES_BT709
+        if srgb { 2 } else { 1 }, // KHR_DF_TRANSFER_SRGB / LINEAR
+        0,                        // straight alpha
+    ]);
+    dfd.extend_from_slice(&[0; 4]); // texelBlockDimension, 1x1x1x1
+    dfd.extend_from_slice(&[4, 0, 0, 0, 0, 0, 0, 0]); // bytesPlane
+
+    for (i, channel) in [0u8, 1, 2, 15].into_iter().enumerate() {
+        // Alpha is never sRGB encoded
+        let qualifiers = if channel == 15 && srgb { 0x10 } else { 0 };
+        dfd.extend_from_slice(&((i * 8) as u16).to_le_bytes());
+        dfd.push(7); // bitLength - 1
+        dfd.push(channel | qualifiers);
+        dfd.extend_from_slice(&[0; 4]); // samplePosition
+        dfd.extend_from_slice(&0u32.to_le_bytes());
+        dfd.extend_from_slice(&255u32.to_le_bytes());
+    }
+
+    dfd
+}
+
+//----------------------------------------
+
+fn key_value(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let length = key.len() + 1 + value.len() + 1;
+    let mut kvd = Vec::with_capacity(4 + length + 3);
+    kvd.extend_from_slice(&(length as u32).to_le_bytes());
+    kvd.extend_from_slice(key);
+    kvd.push(0);
+    kvd.extend_from_slice(value);
+    kvd.push(0);
+    kvd.resize(kvd.len().next_multiple_of(4), 0);
+    kvd
+}
+
+//----------------------------------------
+
+/// The flattened composite Photoshop saves after the layers. Only 8, 16, and
+/// 32 bit Grayscale, Indexed, RGB, and CMYK are handled.
+fn psd(data: &[u8]) -> std::result::Result<DynamicImage, String> {
+    let mut reader = PsdReader { data, position: 0 };
+
+    if reader.bytes(4)? != b"8BPS" {
+        return Err("not a PSD".to_owned());
+    }
+    let version = reader.u16()?;
+    let large = match version {
+        1 => false,
+        2 => true, // PSB
+        _ => return Err(format!("unknown PSD version {}", version)),
+    };
+    reader.bytes(6)?;
+    let channels = reader.u16()? as usize;
+    let height = reader.u32()? as usize;
+    let width = reader.u32()? as usize;
+    let depth = reader.u16()?;
+    let mode = reader.u16()?;
+
+    if !matches!(depth, 8 | 16 | 32) {
+        return Err(format!("{} bit PSD is not supported", depth));
+    }
+
+    let length = reader.u32()? as usize;
+    let palette = reader.bytes(length)?.to_vec();
+    let length = reader.u32()? as usize;
+    reader.bytes(length)?; // Image resources
+    let length = if large {
+        reader.u64()? as usize
+    } else {
+        reader.u32()? as usize
+    };
+    reader.bytes(length)?; // Layers and masks
+
+    let compression = reader.u16()?;
+
+    // The sizes are from the header, check them before allocating
+    let pixels = width
+        .checked_mul(height)
+        .filter(|&pixels| pixels <= MAX_PSD_PIXELS)
+        .ok_or_else(|| format!("{}x{} PSD is too large", width, height))?;
+    let image_size = pixels * channels * depth as usize / 8;
+    let remaining = data.len() - reader.position;
+    let fits = match compression {
+        0 => image_size <= remaining,
+        // A count per row, and PackBits makes at most 128 bytes of 2
+        1 => {
+            channels * height * if large { 4 } else { 2 } <= remaining
+                && image_size / 64 <= remaining
+        }
+        _ => true,
+    };
+    if !fits {
+        return Err(format!("truncated, {} bytes of image data", remaining));
+    }
+
+    let row_bytes = width * depth as usize / 8;
+    let plane_size = row_bytes * height;
+
+    let planes = match compression {
+        0 => (0..channels)
+            .map(|_| reader.bytes(plane_size).map(|b| b.to_vec()))
+            .collect::<std::result::Result<Vec<Vec<u8>>, String>>()?,
+        1 => {
+            let counts = (0..channels * height)
+                .map(|_| {
+                    if large {
+                        reader.u32().map(|c| c as usize)
+                    } else {
+                        reader.u16().map(|c| c as usize)
+                    }
+                })
+                .collect::<std::result::Result<Vec<usize>, String>>()?;
+
+            // Grown as the rows unpack rather than trusting the header
+            let mut planes = vec![Vec::new(); channels];
+            for (row, count) in counts.into_iter().enumerate() {
+                let packed = reader.bytes(count)?;
+                let plane = &mut planes[row / height];
+                let start = plane.len();
+                unpack_bits(packed, plane, row_bytes);
+                plane.resize(start + row_bytes, 0);
+            }
+            planes
+        }
+        _ => return Err(format!("PSD compression {} is not supported", compression)),
+    };
+
+    // Every sample as 0.0..=1.0
+    let sample = |plane: usize, i: usize| -> f32 {
+        let plane = &planes[plane];
+        match depth {
+            8 => plane[i] as f32 / 255.0,
+            16 => u16::from_be_bytes([plane[i * 2], plane[i * 2 + 1]]) as f32 / 65535.0,
+            _ => f32::from_be_bytes([
+                plane[i * 4],
+                plane[i * 4 + 1],
+                plane[i * 4 + 2],
+                plane[i * 4 + 3],
+            ]),
+        }
+    };
+
+    let (color_channels, has_alpha) = match mode {
+        1 | 2 => (1, channels > 1), // Grayscale, Indexed
+        3 => (3, channels > 3),     // RGB
+        4 => (4, channels > 4),     // CMYK
+        _ => return Err(format!("PSD color mode {} is not supported", mode)),
+    };
+    if channels < color_channels {
+        return Err(format!("{} channels for color mode {}", channels, mode));
+    }
+
+    let pixel = |i: usize| -> [f32; 4] {
+        let rgb = match mode {
+            1 => [sample(0, i); 3],
+            2 => {
+                let index = planes[0][i] as usize;
+                let color =
+                    |c: usize| palette.get(c * 256 + index).copied().unwrap_or(0) as f32 / 255.0;
+                [color(0), color(1), color(2)]
+            }
+            3 => [sample(0, i), sample(1, i), sample(2, i)],
+            // Stored inverted, 1.0 is no ink
+            _ => {
+                let k = sample(3, i);
+                [sample(0, i) * k, sample(1, i) * k, sample(2, i) * k]
+            }
+        };
+        let alpha = if has_alpha {
+            sample(color_channels, i)
+        } else {
+            1.0
+        };
+
+        // The composite is matted against white where it is transparent
+        let rgb = if has_alpha && alpha > 0.0 && alpha < 1.0 {
+            rgb.map(|c| ((c - (1.0 - alpha)) / alpha).clamp(0.0, 1.0))
+        } else {
+            rgb
+        };
+
+        [rgb[0], rgb[1], rgb[2], alpha]
+    };
+
+    // Decoded at the document's depth, 32 bit documents are linear, keep
+    // them float like EXR
+    let (w, h) = (width as u32, height as u32);
+    let at = |x: u32, y: u32| pixel(y as usize * width + x as usize);
+    let rgb = |[r, g, b, _]: [f32; 4]| [r, g, b];
+    let to_u8 = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let to_u16 = |c: f32| (c.clamp(0.0, 1.0) * 65535.0).round() as u16;
+
+    Ok(match (depth, has_alpha) {
+        (32, _) => Rgba32FImage::from_fn(w, h, |x, y| Rgba(at(x, y))).into(),
+        (16, true) => ImageBuffer::from_fn(w, h, |x, y| Rgba(at(x, y).map(to_u16))).into(),
+        (16, false) => ImageBuffer::from_fn(w, h, |x, y| Rgb(rgb(at(x, y)).map(to_u16))).into(),
+        (_, true) => RgbaImage::from_fn(w, h, |x, y| Rgba(at(x, y).map(to_u8))).into(),
+        (_, false) => RgbImage::from_fn(w, h, |x, y| Rgb(rgb(at(x, y)).map(to_u8))).into(),
+    })
+}
+
+//----------------------------------------
+
+/// PackBits, stopping at `limit` bytes for rows padded by the writer
+fn unpack_bits(packed: &[u8], out: &mut Vec<u8>, limit: usize) {
+    let start = out.len();
+    let mut i = 0;
+
+    while i < packed.len() && out.len() - start < limit {
+        let n = packed[i] as i8;
+        i += 1;
+        match n {
+            -128 => (),
+            0.. => {
+                let end = (i + n as usize + 1).min(packed.len());
+                out.extend_from_slice(&packed[i..end]);
+                i = end;
+            }
+            _ => {
+                if let Some(&byte) = packed.get(i) {
+                    out.extend(std::iter::repeat_n(byte, (1 - n as isize) as usize));
+                }
+                i += 1;
+            }
+        }
+    }
+
+    out.truncate(start + limit);
+}
+
+//----------------------------------------
+
+struct PsdReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+//----------------------------------------
+
+impl<'a> PsdReader<'a> {
+    fn bytes(&mut self, n: usize) -> std::result::Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(n)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| format!("truncated at byte {}", self.position))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> std::result::Result<u16, String> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> std::result::Result<u32, String> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> std::result::Result<u64, String> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+//----------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAR: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+    #[test]
+    fn bleed_alpha_fills_every_transparent_pixel() {
+        let mut image = RgbaImage::from_pixel(4, 4, CLEAR);
+        image.put_pixel(0, 0, Rgba([0, 255, 0, 255]));
+
+        bleed_alpha(&mut image);
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let alpha = if (x, y) == (0, 0) { 255 } else { 0 };
+            assert_eq!(*pixel, Rgba([0, 255, 0, alpha]), "{} {}", x, y);
+        }
+    }
+
+    #[test]
+    fn bleed_alpha_averages_the_nearer_ring() {
+        let mut image = RgbaImage::from_pixel(7, 1, CLEAR);
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        image.put_pixel(6, 0, Rgba([0, 0, 255, 255]));
+
+        bleed_alpha(&mut image);
+
+        let colors = image
+            .pixels()
+            .map(|pixel| [pixel[0], pixel[2], pixel[3]])
+            .collect::<Vec<[u8; 3]>>();
+        assert_eq!(
+            colors,
+            [
+                [255, 0, 255],
+                [255, 0, 0],
+                [255, 0, 0],
+                // 3 from both sides
+                [127, 127, 0],
+                [0, 255, 0],
+                [0, 255, 0],
+                [0, 255, 255],
+            ]
+        );
+    }
+
+    #[test]
+    fn bleed_alpha_leaves_fully_transparent_images() {
+        let mut image = RgbaImage::from_pixel(3, 3, CLEAR);
+        bleed_alpha(&mut image);
+        assert!(image.pixels().all(|pixel| *pixel == CLEAR));
+    }
+
+    /// An RGB(A) PSD with no palette, resources, or layers
+    fn psd_file(channels: u16, width: u32, depth: u16, compression: u16, data: &[u8]) -> Vec<u8> {
+        let mut file = b"8BPS\x00\x01\x00\x00\x00\x00\x00\x00".to_vec();
+        file.extend(channels.to_be_bytes());
+        file.extend(1u32.to_be_bytes());
+        file.extend(width.to_be_bytes());
+        file.extend(depth.to_be_bytes());
+        file.extend(3u16.to_be_bytes());
+        file.extend([0; 12]);
+        file.extend(compression.to_be_bytes());
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn psd_8_bit_decodes_to_rgb8() {
+        let planes = [255, 0, 0, 128, 10, 20];
+
+        let image = psd(&psd_file(3, 2, 8, 0, &planes)).unwrap();
+
+        let image = match image {
+            DynamicImage::ImageRgb8(image) => image,
+            image => panic!("{:?}", image.color()),
+        };
+        assert_eq!(image.get_pixel(0, 0), &Rgb([255, 0, 10]));
+        assert_eq!(image.get_pixel(1, 0), &Rgb([0, 128, 20]));
+    }
+
+    #[test]
+    fn psd_16_bit_packbits_decodes_to_rgba16() {
+        // A byte count per channel row, then each row as one literal run
+        let mut data = [3u16; 4]
+            .iter()
+            .flat_map(|c| c.to_be_bytes())
+            .collect::<Vec<u8>>();
+        for sample in [0xffffu16, 0x0000, 0x8000, 0xffff] {
+            data.push(1);
+            data.extend(sample.to_be_bytes());
+        }
+
+        let image = psd(&psd_file(4, 1, 16, 1, &data)).unwrap();
+
+        let image = match image {
+            DynamicImage::ImageRgba16(image) => image,
+            image => panic!("{:?}", image.color()),
+        };
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0xffff, 0, 0x8000, 0xffff]));
+    }
+
+    #[test]
+    fn psd_32_bit_stays_float() {
+        let data = [0.25f32, 0.5, 2.0]
+            .iter()
+            .flat_map(|c| c.to_be_bytes())
+            .collect::<Vec<u8>>();
+
+        let image = psd(&psd_file(3, 1, 32, 0, &data)).unwrap();
+
+        let image = match image {
+            DynamicImage::ImageRgba32F(image) => image,
+            image => panic!("{:?}", image.color()),
+        };
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0.25, 0.5, 2.0, 1.0]));
+    }
+
+    #[test]
+    fn psd_sizes_are_checked_against_the_data() {
+        let file = psd_file(3, 100_000, 8, 0, &[0; 16]);
+        assert!(psd(&file).unwrap_err().starts_with("truncated"));
+
+        let file = psd_file(3, 100_000, 8, 1, &[0; 16]);
+        assert!(psd(&file).unwrap_err().starts_with("truncated"));
+    }
+}
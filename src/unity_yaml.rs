@@ -0,0 +1,454 @@
+//----------------------------------------
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+use unity_yaml_rust::parser::{MarkedEventReceiver, Parser};
+use unity_yaml_rust::scanner::{Marker, TScalarStyle};
+use unity_yaml_rust::Event;
+
+use crate::error::{Error, Result};
+
+//----------------------------------------
+
+const UNITY_MACRO: &str = "--- !u!";
+
+//----------------------------------------
+
+/// One `--- !u!CLASS_ID &FILE_ID [stripped]` document of a Unity YAML asset
+#[derive(Debug, Clone, Serialize)]
+pub struct UnityDocument {
+    pub class_id: i32,
+    pub file_id: i64,
+    pub stripped: bool,
+    /// Top level key, "GameObject", "Transform", "MonoBehaviour", etc
+    pub type_name: String,
+    pub body: Value,
+}
+
+//----------------------------------------
+
+/// A document that failed to parse, see `parse_recover`
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentError {
+    /// Position of the document within the asset
+    pub index: usize,
+    /// None when the header itself could not be parsed
+    pub file_id: Option<i64>,
+    pub line: usize,
+    pub message: String,
+}
+
+//----------------------------------------
+
+/// Document text split on the Unity headers, before YAML parsing
+struct RawDocument<'a> {
+    header: Option<&'a str>,
+    body: String,
+    /// 1-based line of the header, body line N is at `line + N`
+    line: usize,
+}
+
+//----------------------------------------
+
+pub fn parse(yaml: &str) -> Result<Vec<UnityDocument>> {
+    let (documents, errors) = parse_recover(yaml);
+
+    match errors.into_iter().next() {
+        Some(err) => Err(Error::MalformedYaml(format!(
+            "line {}: {}",
+            err.line, err.message
+        ))),
+        None => Ok(documents),
+    }
+}
+
+//----------------------------------------
+
+/// Parse every document, keeping the good ones when others fail
+pub fn parse_recover(yaml: &str) -> (Vec<UnityDocument>, Vec<DocumentError>) {
+    let mut documents = Vec::new();
+    let mut errors = Vec::new();
+
+    for (index, raw) in split_documents(yaml).iter().enumerate() {
+        match UnityDocument::from_raw(raw) {
+            Ok(Some(document)) => documents.push(document),
+            Ok(None) => (),
+            Err((file_id, line, message)) => errors.push(DocumentError {
+                index,
+                file_id,
+                line,
+                message,
+            }),
+        }
+    }
+
+    (documents, errors)
+}
+
+//----------------------------------------
+
+fn split_documents(yaml: &str) -> Vec<RawDocument<'_>> {
+    let mut documents = Vec::new();
+    let mut current: Option<RawDocument> = None;
+
+    for (i, line) in yaml.lines().enumerate() {
+        if line.starts_with(UNITY_MACRO) || line.trim_end() == "---" {
+            documents.extend(current.take());
+            current = Some(RawDocument {
+                header: line.strip_prefix(UNITY_MACRO),
+                body: String::new(),
+                line: i + 1,
+            });
+        } else if line.starts_with('%') && current.is_none() {
+            // Ignore %YAML, %TAG, etc
+        } else {
+            let current = current.get_or_insert_with(|| RawDocument {
+                header: None,
+                body: String::new(),
+                line: i,
+            });
+            current.body.push_str(line);
+            current.body.push('\n');
+        }
+    }
+
+    documents.extend(current);
+    documents
+}
+
+//----------------------------------------
+
+/// (file ID if known, line, message)
+type RawError = (Option<i64>, usize, String);
+
+//----------------------------------------
+
+impl UnityDocument {
+    fn from_raw(raw: &RawDocument) -> std::result::Result<Option<UnityDocument>, RawError> {
+        let (class_id, file_id, stripped) = match raw.header {
+            Some(header) => parse_header(header)
+                .ok_or_else(|| (None, raw.line, format!("bad header {:?}", header)))?,
+            None => (0, 0, false),
+        };
+
+        let root = match parse_value(&raw.body, raw.line) {
+            Ok(Some(root)) => root,
+            Ok(None) => return Ok(None),
+            Err((line, message)) => return Err((Some(file_id), line, message)),
+        };
+
+        // Every document is a single "TypeName: { ... }" mapping
+        let (type_name, body) = match root {
+            Value::Mapping(mapping) => match mapping.into_iter().next() {
+                Some((Value::String(type_name), body)) => (type_name, body),
+                _ => return Err((Some(file_id), raw.line, "document has no type".into())),
+            },
+            _ => return Err((Some(file_id), raw.line, "document is not a mapping".into())),
+        };
+
+        Ok(Some(UnityDocument {
+            class_id,
+            file_id,
+            stripped,
+            type_name,
+            body,
+        }))
+    }
+
+    /// The `_class_id`, `_file_id`, `_extra`, `type`, `content` layout `dump`
+    /// has always produced, with fileIDs as strings so they survive JSON
+    /// parsers that read numbers as doubles.
+    pub fn to_value(&self) -> Value {
+        let mut mapping = Mapping::new();
+        mapping.insert("_class_id".into(), self.class_id.into());
+        mapping.insert("_file_id".into(), self.file_id.to_string().into());
+        mapping.insert(
+            "_extra".into(),
+            if self.stripped {
+                "stripped".into()
+            } else {
+                Value::Null
+            },
+        );
+        mapping.insert("type".into(), self.type_name.clone().into());
+        mapping.insert("content".into(), file_ids_to_strings(&self.body));
+        Value::Mapping(mapping)
+    }
+}
+
+//----------------------------------------
+
+pub fn documents_to_value(documents: &[UnityDocument]) -> Value {
+    Value::Sequence(documents.iter().map(UnityDocument::to_value).collect())
+}
+
+//----------------------------------------
+
+/// The fileID of a `{fileID: X, ...}` reference, 0 is a null reference
+pub fn reference_file_id(value: &Value) -> Option<i64> {
+    value.get("fileID").and_then(Value::as_i64)
+}
+
+//----------------------------------------
+
+/// `#[serde(serialize_with)]` for fileIDs, written as strings like `dump`
+pub fn serialize_file_id<S: serde::Serializer>(
+    file_id: &i64,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(file_id)
+}
+
+//----------------------------------------
+
+fn file_ids_to_strings(value: &Value) -> Value {
+    match value {
+        Value::Mapping(mapping) => Value::Mapping(
+            mapping
+                .iter()
+                .map(|(key, value)| match (key.as_str(), value) {
+                    (Some("fileID"), Value::Number(n)) => (key.clone(), n.to_string().into()),
+                    _ => (key.clone(), file_ids_to_strings(value)),
+                })
+                .collect(),
+        ),
+        Value::Sequence(sequence) => {
+            Value::Sequence(sequence.iter().map(file_ids_to_strings).collect())
+        }
+        _ => value.clone(),
+    }
+}
+
+//----------------------------------------
+
+/// "CLASS_ID &FILE_ID [stripped]", the part after "--- !u!"
+fn parse_header(header: &str) -> Option<(i32, i64, bool)> {
+    let mut chunks = header.split_whitespace();
+    let class_id = chunks.next()?.parse().ok()?;
+    let file_id = chunks.next()?.strip_prefix('&')?.parse().ok()?;
+    let stripped = match chunks.next() {
+        None => false,
+        Some("stripped") => true,
+        Some(_) => return None,
+    };
+
+    Some((class_id, file_id, stripped))
+}
+
+//----------------------------------------
+
+/// Errors are (line, message)
+fn parse_value(body: &str, line: usize) -> std::result::Result<Option<Value>, (usize, String)> {
+    let mut builder = ValueBuilder::default();
+    let mut parser = Parser::new(body.chars());
+
+    parser.load(&mut builder, false).map_err(|err| {
+        // Display adds "at line N column M" relative to the document body
+        let message = err.to_string();
+        let message = message.split(" at line ").next().unwrap_or("");
+        (line + err.marker().line(), message.to_owned())
+    })?;
+
+    Ok(builder.root)
+}
+
+//----------------------------------------
+
+enum Node {
+    Sequence(Vec<Value>, usize),
+    /// Mapping, key waiting for its value, keys seen more than once, anchor
+    Mapping(Mapping, Option<Value>, Vec<Value>, usize),
+}
+
+//----------------------------------------
+
+/// Builds a `serde_yaml::Value` from parser events, keeping every value of
+/// a duplicated key as a list instead of the last one winning.
+#[derive(Default)]
+struct ValueBuilder {
+    stack: Vec<Node>,
+    anchors: HashMap<usize, Value>,
+    root: Option<Value>,
+}
+
+//----------------------------------------
+
+impl ValueBuilder {
+    fn is_guid_value(&self) -> bool {
+        match self.stack.last() {
+            Some(Node::Mapping(_, Some(key), _, _)) => key.as_str() == Some("guid"),
+            _ => false,
+        }
+    }
+
+    fn push(&mut self, value: Value, anchor: usize) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, value.clone());
+        }
+
+        match self.stack.last_mut() {
+            None => self.root = Some(value),
+            Some(Node::Sequence(sequence, _)) => sequence.push(value),
+            Some(Node::Mapping(mapping, key, duplicates, _)) => match key.take() {
+                None => *key = Some(value),
+                Some(key) => match mapping.get_mut(&key) {
+                    None => {
+                        mapping.insert(key, value);
+                    }
+                    Some(Value::Sequence(values)) if duplicates.contains(&key) => {
+                        values.push(value);
+                    }
+                    Some(existing) => {
+                        let first = std::mem::replace(existing, Value::Null);
+                        *existing = Value::Sequence(vec![first, value]);
+                        duplicates.push(key);
+                    }
+                },
+            },
+        }
+    }
+}
+
+//----------------------------------------
+
+impl MarkedEventReceiver for ValueBuilder {
+    fn on_event(&mut self, event: Event, _mark: Marker) {
+        match event {
+            Event::SequenceStart(anchor) => self.stack.push(Node::Sequence(Vec::new(), anchor)),
+            Event::MappingStart(anchor, _block) => {
+                self.stack
+                    .push(Node::Mapping(Mapping::new(), None, Vec::new(), anchor))
+            }
+            Event::SequenceEnd | Event::MappingEnd => match self.stack.pop() {
+                Some(Node::Sequence(sequence, anchor)) => {
+                    self.push(Value::Sequence(sequence), anchor)
+                }
+                Some(Node::Mapping(mapping, _key, _duplicates, anchor)) => {
+                    self.push(Value::Mapping(mapping), anchor)
+                }
+                None => (),
+            },
+            Event::Scalar(value, style, anchor, _tag) => {
+                // GUIDs can be all digits, or look like floats, "0000e000..."
+                let value = if self.is_guid_value() {
+                    Value::String(value)
+                } else {
+                    resolve_scalar(value, style)
+                };
+                self.push(value, anchor)
+            }
+            Event::Alias(anchor) => {
+                let value = self.anchors.get(&anchor).cloned().unwrap_or(Value::Null);
+                self.push(value, 0)
+            }
+            _ => (),
+        }
+    }
+}
+
+//----------------------------------------
+
+fn resolve_scalar(value: String, style: TScalarStyle) -> Value {
+    if style != TScalarStyle::Plain {
+        return Value::String(value);
+    }
+
+    match value.as_str() {
+        "" | "~" | "null" | "Null" | "NULL" => return Value::Null,
+        "true" | "True" | "TRUE" => return Value::Bool(true),
+        "false" | "False" | "FALSE" => return Value::Bool(false),
+        ".inf" | ".Inf" | ".INF" | "+.inf" | "+.Inf" | "+.INF" => {
+            return Value::Number(f64::INFINITY.into())
+        }
+        "-.inf" | "-.Inf" | "-.INF" => return Value::Number(f64::NEG_INFINITY.into()),
+        ".nan" | ".NaN" | ".NAN" => return Value::Number(f64::NAN.into()),
+        _ => (),
+    }
+
+    if let Ok(n) = value.parse::<i64>() {
+        return Value::Number(n.into());
+    }
+    if let Ok(n) = value.parse::<u64>() {
+        return Value::Number(n.into());
+    }
+
+    // Rust also accepts "inf", "NaN", etc, which YAML keeps as strings
+    let numeric = value
+        .trim_start_matches(['-', '+'])
+        .starts_with(|c: char| c.is_ascii_digit() || c == '.');
+    if numeric {
+        if let Ok(n) = value.parse::<f64>() {
+            return Value::Number(n.into());
+        }
+    }
+
+    Value::String(value)
+}
+
+//----------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFAB: &str = "%YAML 1.1
+%TAG !u! tag:unity3d.com,2011:
+--- !u!1 &100
+GameObject:
+  m_Name: Cube
+--- !u!4 &8765432109876543210 stripped
+Transform:
+  m_CorrespondingSourceObject: {fileID: 400, guid: 0123456789abcdef0123456789abcdef, type: 3}
+";
+
+    //----------------------------------------
+
+    #[test]
+    fn parses_headers_and_bodies() {
+        let documents = parse(PREFAB).unwrap();
+
+        assert_eq!(documents.len(), 2);
+        assert_eq!(documents[0].class_id, 1);
+        assert_eq!(documents[0].file_id, 100);
+        assert!(!documents[0].stripped);
+        assert_eq!(documents[0].type_name, "GameObject");
+        assert_eq!(documents[0].body["m_Name"], Value::from("Cube"));
+
+        assert_eq!(documents[1].file_id, 8765432109876543210);
+        assert!(documents[1].stripped);
+        assert_eq!(
+            documents[1].body["m_CorrespondingSourceObject"]["fileID"],
+            Value::from(400)
+        );
+    }
+
+    #[test]
+    fn recovers_the_documents_around_a_malformed_one() {
+        let yaml = PREFAB.replacen("  m_Name: Cube\n", "  m_Name: [Cube\n", 1);
+
+        let (documents, errors) = parse_recover(&yaml);
+
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].file_id, 8765432109876543210);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].index, 0);
+        assert_eq!(errors[0].file_id, Some(100));
+        // Within the body, past the header on line 3
+        assert!(errors[0].line > 3);
+
+        assert!(matches!(parse(&yaml), Err(Error::MalformedYaml(_))));
+    }
+
+    #[test]
+    fn reports_a_bad_header_without_a_file_id() {
+        let yaml = PREFAB.replacen("--- !u!1 &100", "--- !u!x &100", 1);
+
+        let (documents, errors) = parse_recover(&yaml);
+
+        assert_eq!(documents.len(), 1);
+        assert_eq!(errors[0].file_id, None);
+        assert_eq!(errors[0].line, 3);
+    }
+}
@@ -0,0 +1,338 @@
+//----------------------------------------
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::package::Package;
+
+//----------------------------------------
+
+/// Files Unity writes into a GUID directory
+const KNOWN_FILES: [&str; 4] = ["asset", "asset.meta", "pathname", "preview.png"];
+
+/// Files Unity writes next to the GUID directories
+const KNOWN_ROOT_FILES: [&str; 1] = [".icon.png"];
+
+//----------------------------------------
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ValidationReport {
+    pub errors: usize,
+    pub warnings: usize,
+    pub problems: Vec<Problem>,
+}
+
+//----------------------------------------
+
+/// A structural problem `validate` found
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    /// GUID directory, or the entry path for files outside one
+    pub guid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pathname: Option<String>,
+    /// "error" or "warning"
+    pub severity: &'static str,
+    /// "bad_guid", "missing_pathname", "missing_meta", "meta_guid_mismatch",
+    /// "duplicate_pathname", "outside_assets", "folder_with_asset", or
+    /// "unknown_file"
+    pub problem: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+//----------------------------------------
+
+/// What validate needs of each GUID directory
+#[derive(Debug, Default)]
+struct GuidDir {
+    pathname: Option<String>,
+    meta: Option<Vec<u8>>,
+    has_asset: bool,
+    unknown: Vec<String>,
+}
+
+//----------------------------------------
+
+/// Check the layout of every entry, not just the ones other commands read.
+/// Unknown files are warnings, everything else is an error.
+pub fn validate_package(package: &Package) -> Result<ValidationReport> {
+    let mut dirs = BTreeMap::<String, GuidDir>::new();
+    let mut bad_guids = BTreeSet::<String>::new();
+    let mut unknown_root = Vec::<String>::new();
+
+    for file in package.clone().open()?.entries()? {
+        let mut file = file?;
+
+        let guid = file.guid()?;
+        let file_name = file.file_name()?;
+
+        if guid.is_empty() || guid == "." {
+            continue;
+        }
+
+        // Next to the GUID directories, or a directory entry
+        if file_name.is_empty() {
+            if !file.is_dir() {
+                unknown_root.push(guid);
+            } else if is_guid(&guid) {
+                dirs.entry(guid).or_default();
+            } else {
+                bad_guids.insert(guid);
+            }
+            continue;
+        }
+
+        if !is_guid(&guid) {
+            bad_guids.insert(guid);
+            continue;
+        }
+
+        let dir = dirs.entry(guid).or_default();
+        match file_name.as_str() {
+            "pathname" => {
+                let mut buffer = Vec::new();
+                file.read_to_end(&mut buffer)?;
+                let pathname = String::from_utf8_lossy(&buffer);
+                dir.pathname = Some(pathname.split('\n').next().unwrap_or("").to_owned());
+            }
+            "asset.meta" => {
+                let mut buffer = Vec::new();
+                file.read_to_end(&mut buffer)?;
+                dir.meta = Some(buffer);
+            }
+            "asset" => dir.has_asset = true,
+            name if KNOWN_FILES.contains(&name) => (),
+            name => dir.unknown.push(name.to_owned()),
+        }
+    }
+
+    let mut problems = Vec::new();
+    let mut report = |guid: &str, pathname: &Option<String>, problem, detail| {
+        let severity = match problem {
+            "unknown_file" => "warning",
+            _ => "error",
+        };
+        problems.push(Problem {
+            guid: guid.to_owned(),
+            pathname: pathname.clone(),
+            severity,
+            problem,
+            detail,
+        });
+    };
+
+    for name in unknown_root {
+        if !KNOWN_ROOT_FILES.contains(&name.as_str()) {
+            report(&name, &None, "unknown_file", None);
+        }
+    }
+
+    for guid in &bad_guids {
+        report(
+            guid,
+            &None,
+            "bad_guid",
+            Some("not 32 hex digits".to_owned()),
+        );
+    }
+
+    // Pathnames compared ignoring case, like Windows and macOS projects do
+    let mut by_pathname = HashMap::<String, Vec<&str>>::new();
+
+    for (guid, dir) in &dirs {
+        let pathname = &dir.pathname;
+
+        match pathname {
+            None => report(guid, pathname, "missing_pathname", None),
+            Some(name) => {
+                if !is_project_path(name) {
+                    report(guid, pathname, "outside_assets", None);
+                }
+                by_pathname
+                    .entry(name.to_lowercase())
+                    .or_default()
+                    .push(guid);
+            }
+        }
+
+        match &dir.meta {
+            None => report(guid, pathname, "missing_meta", None),
+            Some(meta) => {
+                let meta_guid = std::str::from_utf8(meta)
+                    .ok()
+                    .and_then(crate::asset_meta_guid);
+                if meta_guid.as_deref() != Some(guid.as_str()) {
+                    let detail = match meta_guid {
+                        Some(meta_guid) => format!("meta has guid {}", meta_guid),
+                        None => "meta has no guid".to_owned(),
+                    };
+                    report(guid, pathname, "meta_guid_mismatch", Some(detail));
+                }
+
+                if dir.has_asset && crate::asset_meta_is_folder(meta) {
+                    report(guid, pathname, "folder_with_asset", None);
+                }
+            }
+        }
+
+        for name in &dir.unknown {
+            report(guid, pathname, "unknown_file", Some(name.clone()));
+        }
+    }
+
+    for (guid, dir) in &dirs {
+        let Some(pathname) = &dir.pathname else {
+            continue;
+        };
+        let others = by_pathname[&pathname.to_lowercase()]
+            .iter()
+            .filter(|other| **other != guid.as_str())
+            .copied()
+            .collect::<Vec<&str>>();
+        if !others.is_empty() {
+            let detail = format!("also {}", others.join(", "));
+            report(guid, &dir.pathname, "duplicate_pathname", Some(detail));
+        }
+    }
+
+    let errors = problems.iter().filter(|p| p.severity == "error").count();
+    Ok(ValidationReport {
+        errors,
+        warnings: problems.len() - errors,
+        problems,
+    })
+}
+
+//----------------------------------------
+
+fn is_guid(name: &str) -> bool {
+    name.len() == 32 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+//----------------------------------------
+
+/// Unity only imports below Assets/ and Packages/
+fn is_project_path(pathname: &str) -> bool {
+    ["Assets", "Packages"].iter().any(|root| {
+        pathname == *root
+            || pathname
+                .strip_prefix(root)
+                .is_some_and(|rest| rest.starts_with('/'))
+    })
+}
+
+//----------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{entry, guid, meta, package, tar, Entry};
+
+    fn validate(files: &[(String, String)]) -> ValidationReport {
+        let entries = files
+            .iter()
+            .map(|(path, data)| entry(path.as_str(), data))
+            .collect::<Vec<Entry>>();
+        validate_package(&package(&tar(&entries))).unwrap()
+    }
+
+    /// (guid, problem) of every finding
+    fn problems(report: &ValidationReport) -> Vec<(String, &'static str)> {
+        let mut problems = report
+            .problems
+            .iter()
+            .map(|p| (p.guid.clone(), p.problem))
+            .collect::<Vec<_>>();
+        problems.sort();
+        problems
+    }
+
+    fn asset(guid: &str, pathname: &str) -> Vec<(String, String)> {
+        vec![
+            (format!("{}/pathname", guid), pathname.to_owned()),
+            (format!("{}/asset", guid), "data".to_owned()),
+            (format!("{}/asset.meta", guid), meta(guid)),
+        ]
+    }
+
+    //----------------------------------------
+
+    #[test]
+    fn a_well_formed_package_has_no_problems() {
+        let mut files = asset(&guid(1), "Assets/A.txt");
+        files.push((format!("{}/preview.png", guid(1)), "png".to_owned()));
+        files.push((".icon.png".to_owned(), "png".to_owned()));
+
+        let report = validate(&files);
+
+        assert_eq!((report.errors, report.warnings), (0, 0));
+    }
+
+    #[test]
+    fn reports_missing_and_mismatched_files() {
+        let (a, b, c) = (guid(1), guid(2), guid(3));
+        let files = vec![
+            (format!("{}/asset.meta", a), meta(&a)),
+            (format!("{}/pathname", b), "Assets/B.txt".to_owned()),
+            (format!("{}/pathname", c), "Assets/C.txt".to_owned()),
+            (format!("{}/asset.meta", c), meta(&a)),
+            ("not-a-guid/pathname".to_owned(), "Assets/D.txt".to_owned()),
+        ];
+
+        let report = validate(&files);
+
+        assert_eq!(
+            problems(&report),
+            vec![
+                (a, "missing_pathname"),
+                (b, "missing_meta"),
+                (c, "meta_guid_mismatch"),
+                ("not-a-guid".to_owned(), "bad_guid"),
+            ]
+        );
+        assert_eq!(report.errors, 4);
+    }
+
+    #[test]
+    fn reports_pathname_problems() {
+        let (a, b, c) = (guid(1), guid(2), guid(3));
+        let mut files = asset(&a, "Assets/Rock.prefab");
+        files.extend(asset(&b, "Assets/rock.prefab"));
+        files.extend(asset(&c, "ProjectSettings/Tags.asset"));
+
+        let report = validate(&files);
+
+        assert_eq!(
+            problems(&report),
+            vec![
+                (a, "duplicate_pathname"),
+                (b, "duplicate_pathname"),
+                (c, "outside_assets"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_folders_with_assets_and_unknown_files() {
+        let a = guid(1);
+        let mut files = asset(&a, "Assets/Art");
+        files[2].1.push_str("folderAsset: yes\n");
+        files.push((format!("{}/notes.txt", a), "?".to_owned()));
+        files.push(("README".to_owned(), "?".to_owned()));
+
+        let report = validate(&files);
+
+        assert_eq!(
+            problems(&report),
+            vec![
+                (a.clone(), "folder_with_asset"),
+                (a, "unknown_file"),
+                ("README".to_owned(), "unknown_file"),
+            ]
+        );
+        assert_eq!((report.errors, report.warnings), (1, 2));
+    }
+}
@@ -0,0 +1,148 @@
+//----------------------------------------
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tar::{Builder, EntryType, Header};
+use walkdir::WalkDir;
+
+use crate::error::{Error, Result};
+
+//----------------------------------------
+
+/// Writes the `<guid>/asset`, `<guid>/asset.meta`, `<guid>/pathname`
+/// layout Unity expects into a gzipped tar.
+pub struct PackageWriter<W: Write> {
+    builder: Builder<GzEncoder<W>>,
+}
+
+//----------------------------------------
+
+impl PackageWriter<File> {
+    pub fn create(path: &Path) -> Result<PackageWriter<File>> {
+        Ok(PackageWriter::new(File::create(path)?))
+    }
+}
+
+//----------------------------------------
+
+impl<W: Write> PackageWriter<W> {
+    pub fn new(writer: W) -> PackageWriter<W> {
+        PackageWriter {
+            builder: Builder::new(GzEncoder::new(writer, Compression::default())),
+        }
+    }
+
+    /// Add a single asset, folders have no `asset` data
+    pub fn add_asset(
+        &mut self,
+        guid: &str,
+        pathname: &str,
+        asset: Option<&[u8]>,
+        meta: &[u8],
+    ) -> Result<()> {
+        if let Some(asset) = asset {
+            self.append(&format!("{guid}/asset"), asset)?;
+        }
+        self.append(&format!("{guid}/asset.meta"), meta)?;
+        self.append(&format!("{guid}/pathname"), pathname.as_bytes())
+    }
+
+    /// Add the `preview.png` thumbnail of an asset
+    pub fn add_preview(&mut self, guid: &str, png: &[u8]) -> Result<()> {
+        self.append(&format!("{guid}/preview.png"), png)
+    }
+
+    /// Walk `project/dir` and add every file and folder that has a `.meta`
+    /// next to it. Returns the paths skipped because their `.meta` is missing.
+    pub fn add_project(&mut self, project: &Path, dir: &str) -> Result<Vec<PathBuf>> {
+        let mut skipped = Vec::new();
+
+        let walker = WalkDir::new(project.join(dir))
+            .sort_by_file_name()
+            .into_iter()
+            // Unity ignores hidden files and folders, and those ending in ~
+            .filter_entry(|entry| {
+                let name = entry.file_name().to_string_lossy();
+                entry.depth() == 0 || !(name.starts_with('.') || name.ends_with('~'))
+            });
+
+        for entry in walker {
+            let entry = entry?;
+            let path = entry.path();
+
+            if path.extension().is_some_and(|ext| ext == "meta") {
+                continue;
+            }
+
+            let mut meta_path = path.as_os_str().to_owned();
+            meta_path.push(".meta");
+            let meta_path = PathBuf::from(meta_path);
+
+            if !meta_path.is_file() {
+                // "Assets" itself never has a .meta
+                if entry.depth() > 0 {
+                    skipped.push(path.to_path_buf());
+                }
+                continue;
+            }
+
+            let meta = fs::read(&meta_path)?;
+            let guid = meta_file_guid(&meta, &meta_path)?;
+            let pathname = project_pathname(project, path)?;
+
+            if entry.file_type().is_dir() {
+                self.add_asset(&guid, &pathname, None, &meta)?;
+            } else {
+                let asset = fs::read(path)?;
+                self.add_asset(&guid, &pathname, Some(&asset), &meta)?;
+            }
+        }
+
+        Ok(skipped)
+    }
+
+    pub fn finish(self) -> Result<W> {
+        Ok(self.builder.into_inner()?.finish()?)
+    }
+
+    fn append(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        Ok(self.builder.append_data(&mut header, path, data)?)
+    }
+}
+
+//----------------------------------------
+
+fn meta_file_guid(meta: &[u8], meta_path: &Path) -> Result<String> {
+    std::str::from_utf8(meta)
+        .ok()
+        .and_then(crate::asset_meta_guid)
+        .ok_or_else(|| Error::BadMeta(format!("No guid in {}", meta_path.display())))
+}
+
+//----------------------------------------
+
+fn project_pathname(project: &Path, path: &Path) -> Result<String> {
+    let relative = path.strip_prefix(project).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is outside {}", path.display(), project.display()),
+        )
+    })?;
+
+    // Pathnames always use forward slashes, even when packed on Windows
+    Ok(relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+//----------------------------------------